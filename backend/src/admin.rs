@@ -0,0 +1,327 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::PathBuf,
+    str::FromStr,
+    time::Duration,
+};
+
+use mio::Token;
+use tracing::{info, warn};
+
+use crate::config::TICK_MILLIS;
+use crate::game::{player_char, Board, PlayerId};
+use crate::network::{self, Error};
+use crate::GameState;
+
+/// Commands accepted on the admin port, see the README for their syntax.
+#[derive(Debug, Clone)]
+pub(crate) enum AdminCommand {
+    Auth(String),
+    Pause,
+    Resume,
+    Tick(Duration),
+    Kick(PlayerId),
+    Ban(PlayerId),
+    Reset,
+    Load(PathBuf),
+    Start,
+    Stop,
+    Dump,
+}
+
+impl FromStr for AdminCommand {
+    type Err = Error;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let mut args = line.splitn(2, ' ');
+        let args = std::array::from_fn(|_| args.next().unwrap_or_default());
+        let player = |id: &str| id.parse().map_err(|_| Error::InvalidArgument);
+        match args {
+            ["AUTH", token] => Ok(AdminCommand::Auth(token.to_owned())),
+            ["PAUSE", ""] => Ok(AdminCommand::Pause),
+            ["RESUME", ""] => Ok(AdminCommand::Resume),
+            ["TICK", millis] => match millis.parse() {
+                Ok(millis) if TICK_MILLIS.contains(&millis) => Ok(AdminCommand::Tick(Duration::from_millis(millis))),
+                _ => Err(Error::InvalidArgument),
+            },
+            ["KICK", id] => Ok(AdminCommand::Kick(player(id)?)),
+            ["BAN", id] => Ok(AdminCommand::Ban(player(id)?)),
+            ["RESET", ""] => Ok(AdminCommand::Reset),
+            ["LOAD", path] if !path.is_empty() => Ok(AdminCommand::Load(path.into())),
+            ["START", ""] => Ok(AdminCommand::Start),
+            ["STOP", ""] => Ok(AdminCommand::Stop),
+            ["DUMP", ""] => Ok(AdminCommand::Dump),
+            _ => Err(Error::UnknownCommand),
+        }
+    }
+}
+
+impl GameState {
+    /// Runs the commands of all admins in the order they were received and answers each of them.
+    pub(crate) fn process_admin_input(&mut self) {
+        let mut commands = Vec::new();
+        for (&token, admin) in self.admins.iter_mut() {
+            loop {
+                match network::parse_line(&mut admin.incoming, AdminCommand::from_str) {
+                    Err(Error::WouldBlock) => break,
+                    command => commands.push((token, command)),
+                }
+            }
+        }
+        for (token, command) in commands {
+            let Some(admin) = self.admins.get(&token) else { continue };
+            let authenticated = admin.authenticated;
+            let authenticates = matches!(command, Ok(AdminCommand::Auth(_)));
+            let result = match command {
+                Ok(AdminCommand::Auth(secret)) => self.authenticate(&secret),
+                Ok(_) if !authenticated => Err(Error::Unauthorized),
+                Ok(command) => self.execute(command),
+                Err(e) => Err(e),
+            };
+            let Some(admin) = self.admins.get_mut(&token) else { continue };
+            match result {
+                Ok(lines) => {
+                    admin.authenticated |= authenticates;
+                    lines.iter().for_each(|line| admin.send_line(line));
+                    admin.send_line("OK");
+                }
+                Err(e) => {
+                    admin.send_line(e.response().trim_end());
+                    if !authenticated {
+                        // No second guesses for the token
+                        warn!(addr = %admin.addr, "admin failed to authenticate: {e}");
+                        let _ = admin.flush();
+                        self.admins.remove(&token);
+                        self.metrics.dropped("admin_unauthorized");
+                    }
+                }
+            }
+        }
+    }
+
+    fn authenticate(&self, secret: &str) -> Result<Vec<String>, Error> {
+        match &self.config.admin_token {
+            Some(expected) if expected == secret => Ok(Vec::new()),
+            _ => Err(Error::Unauthorized),
+        }
+    }
+
+    fn execute(&mut self, command: AdminCommand) -> Result<Vec<String>, Error> {
+        info!(?command, "admin command");
+        match command {
+            AdminCommand::Auth(_) => unreachable!("handled by process_admin_input"),
+            AdminCommand::Pause => self.paused = true,
+            AdminCommand::Resume => self.paused = false,
+            AdminCommand::Tick(tick) => self.tick = tick,
+            AdminCommand::Kick(id) => self.kick(id)?,
+            AdminCommand::Ban(id) => self.ban(id)?,
+            AdminCommand::Reset => self.reset(),
+            AdminCommand::Load(path) => self.load_map(&std::fs::read_to_string(path)?)?,
+            AdminCommand::Start => self.start(),
+            AdminCommand::Stop => self.running = false,
+            AdminCommand::Dump => return Ok(self.dump()),
+        }
+        Ok(Vec::new())
+    }
+
+    /// The connections controlling a player, more than one if it is played by a team.
+    fn player_tokens(&self, id: PlayerId) -> Result<Vec<Token>, Error> {
+        let users = self.users.iter().filter(|(_, user)| user.id == Some(id));
+        let tokens: Vec<_> = users.map(|(&token, _)| token).collect();
+        if tokens.is_empty() {
+            return Err(Error::InvalidArgument);
+        }
+        Ok(tokens)
+    }
+
+    /// Disconnects all connections of a player, freeing the slot for the next waiting connection.
+    fn kick(&mut self, id: PlayerId) -> Result<(), Error> {
+        for token in self.player_tokens(id)? {
+            let user = self.users.get_mut(&token).expect("found above");
+            let _ = user.send_error(&Error::Kicked);
+            self.disconnected.push((token, "kicked"));
+        }
+        Ok(())
+    }
+
+    /// Disconnects all connections from the IP addresses of a player and refuses new ones.
+    fn ban(&mut self, id: PlayerId) -> Result<(), Error> {
+        let ips: BTreeSet<_> = self.player_tokens(id)?.iter().map(|token| self.users[token].addr.ip()).collect();
+        self.bans.extend(&ips);
+        for (&token, user) in self.users.iter_mut().filter(|(_, user)| ips.contains(&user.addr.ip())) {
+            let _ = user.send_error(&Error::Banned);
+            self.disconnected.push((token, "banned"));
+        }
+        Ok(())
+    }
+
+    /// Clears the board, going back to the current map, along with the saved stones of the players.
+    fn reset(&mut self) {
+        self.board = self.map.clone();
+        self.board.reset_timer();
+        self.history.reset(&self.board);
+        self.budgets.fill(0);
+        self.handicaps.fill(0);
+    }
+
+    /// Replaces the map and resets the board to it.
+    fn load_map(&mut self, map: &str) -> Result<(), Error> {
+        self.map = Board::from_map(map).map_err(Error::InvalidMap)?;
+        self.reset();
+        Ok(())
+    }
+
+    /// Starts a new game on a fresh board, unless one is running already.
+    fn start(&mut self) {
+        if !self.running {
+            self.reset();
+            self.running = true;
+            self.admit_waiting();
+        }
+    }
+
+    /// Describes the state of the server, one line per item.
+    fn dump(&self) -> Vec<String> {
+        let yes_no = |b| if b { "yes" } else { "no" };
+        let board = &self.board;
+        let mut lines = vec![
+            format!("RUNNING {}", yes_no(self.running)),
+            format!("PAUSED {}", yes_no(self.paused)),
+            format!("TICK {}", self.tick.as_millis()),
+            format!("TURN {}", self.history.turn()),
+            format!("BOARD {} {} {}", board.width, board.height, board.serialize()),
+        ];
+        for user in self.users.values() {
+            if let Some(id) = user.id {
+                let username = user.username.as_deref().unwrap_or("-");
+                lines.push(format!("PLAYER {id} {} {} {username}", player_char(id), user.addr));
+            }
+        }
+        let stones = board.stones();
+        let mut teams = BTreeMap::new();
+        for user in self.users.values() {
+            let team = user.username.as_ref().and_then(|username| self.config.teams.get(username));
+            if let (Some(id), Some(team)) = (user.id, team) {
+                teams.insert(team, id);
+            }
+        }
+        for (team, id) in teams {
+            let stones = stones.get(&id).copied().unwrap_or_default();
+            lines.push(format!("TEAM {id} {} {team} {stones}", player_char(id)));
+        }
+        for (position, user) in self.waiting.iter().filter_map(|token| self.users.get(token)).enumerate() {
+            lines.push(format!("WAITING {} {}", position + 1, user.addr));
+        }
+        lines.extend(self.reserved.iter().map(|(username, id)| format!("RESERVED {id} {username}")));
+        lines.extend(self.frontends.values().map(|frontend| format!("FRONTEND {}", frontend.addr)));
+        lines.extend(self.bans.iter().map(|ip| format!("BANNED {ip}")));
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::BufRead;
+
+    use super::*;
+    use crate::config::Config;
+    use crate::game::Suicide;
+    use crate::network::Admin;
+    use crate::tests::{connect, team_game};
+
+    #[test]
+    fn kicks_disconnect_the_whole_team() {
+        let mut game = team_game(None);
+        connect(&mut game, "alice");
+        connect(&mut game, "bob");
+        let carol = connect(&mut game, "carol");
+        game.kick(0).expect("player 0 plays");
+        game.remove_disconnected_users();
+        assert_eq!(game.users.keys().copied().collect::<Vec<_>>(), [carol]);
+        assert!(game.players[0].is_none());
+        assert!(game.kick(0).is_err());
+    }
+
+    #[test]
+    fn bans_refuse_the_whole_team() {
+        let mut game = team_game(None);
+        connect(&mut game, "alice");
+        connect(&mut game, "bob");
+        game.ban(0).expect("player 0 plays");
+        game.remove_disconnected_users();
+        assert!(game.users.is_empty());
+        assert!(game.bans.contains(&"127.0.0.1".parse().expect("valid ip")));
+    }
+
+    /// Connects an admin to `game` and returns the client side of the connection.
+    fn connect_admin(game: &mut GameState) -> (Token, std::net::TcpStream) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("free port");
+        let client = std::net::TcpStream::connect(listener.local_addr().expect("bound")).expect("connected");
+        let (stream, addr) = listener.accept().expect("accepted");
+        stream.set_nonblocking(true).expect("nonblocking");
+        client.set_read_timeout(Some(Duration::from_secs(5))).expect("timeout");
+        let token = Token(game.admins.len());
+        game.admins.insert(token, Admin::new(mio::net::TcpStream::from_std(stream), addr));
+        (token, client)
+    }
+
+    /// Sends the admin commands and returns the answer, which ends with `OK` or an `ERROR`.
+    fn command(game: &mut GameState, (token, client): &mut (Token, std::net::TcpStream), lines: &str) -> String {
+        game.admins.get_mut(token).expect("connected").incoming.extend(lines.as_bytes());
+        game.process_admin_input();
+        if let Some(admin) = game.admins.get_mut(token) {
+            admin.flush().expect("flushed");
+        }
+        let mut answer = String::new();
+        let mut reader = std::io::BufReader::new(&*client);
+        while !answer.ends_with("OK\n") && !answer.contains("ERROR") {
+            reader.read_line(&mut answer).expect("answer");
+        }
+        answer
+    }
+
+    fn admin_game() -> GameState {
+        GameState::new(5, Config { admin_token: Some("secret".to_owned()), ..Config::default() })
+    }
+
+    #[test]
+    fn admins_authenticate_with_the_token() {
+        let mut game = admin_game();
+        let mut admin = connect_admin(&mut game);
+        assert_eq!(command(&mut game, &mut admin, "AUTH secret\n"), "OK\n");
+        assert_eq!(command(&mut game, &mut admin, "PAUSE\n"), "OK\n");
+        assert!(game.paused);
+        // Mistakes after authenticating keep the connection open
+        assert_eq!(command(&mut game, &mut admin, "TICK 0\n"), "ERROR 101 invalid argument\n");
+        assert!(game.admins.contains_key(&admin.0));
+    }
+
+    #[test]
+    fn wrong_tokens_close_the_connection() {
+        let mut game = admin_game();
+        let mut admin = connect_admin(&mut game);
+        assert_eq!(command(&mut game, &mut admin, "AUTH guess\n"), "ERROR 106 not authenticated\n");
+        assert!(game.admins.is_empty());
+    }
+
+    #[test]
+    fn commands_before_authenticating_are_refused() {
+        let mut game = admin_game();
+        let mut admin = connect_admin(&mut game);
+        assert_eq!(command(&mut game, &mut admin, "PAUSE\nAUTH secret\n"), "ERROR 106 not authenticated\n");
+        assert!(!game.paused);
+        assert!(game.admins.is_empty());
+    }
+
+    #[test]
+    fn resets_clear_the_board_and_the_saved_stones() {
+        let mut game = GameState::new(5, Config { budget: Some(5), handicap: 2, ..Config::default() });
+        connect(&mut game, "alice");
+        game.budgets[0] = 3;
+        game.board.try_place(0, 0, 0, Suicide::Before).expect("legal move");
+        game.board.place_stones(&[(0, 0, 0)]);
+        game.reset();
+        assert!(game.board.stones().is_empty());
+        assert_eq!((game.budgets[0], game.handicaps[0]), (0, 0));
+    }
+}
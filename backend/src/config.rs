@@ -0,0 +1,291 @@
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::game::{Capture, Suicide, MAX_PLAYERS};
+
+pub(crate) const USAGE: &str = "\
+Usage: goto [OPTIONS]
+
+Options:
+  --tick <MS>                   Milliseconds per turn [default: 100]
+  --admin-token <TOKEN>         Enables the admin port, admins authenticate with `AUTH <TOKEN>`
+  --admin-port <PORT>           Port of the admin channel [default: 1314]
+  --metrics-port <PORT>         Serves Prometheus metrics at `/metrics` on this port
+  --snapshot <PATH>             Saves the game to this file periodically
+  --snapshot-interval <SECS>    Seconds between snapshots [default: 60]
+  --resume                      Continues the game saved in the snapshot file instead of starting a new one
+  --fog <RADIUS>                Fog of war: bots only see the fields within this distance of their own stones
+  --teams <PATH>                Team play: bots listed in this file share a player with their team
+  --team-moves <N>              Stones a team may place per tick [default: one per connection]
+  --budget <MAX>                Action budget: players earn a stone per turn, save up to MAX and spend several at once
+  --capture-bonus <N>           Stones a player earns per captured stone with --budget [default: 1]
+  --grow <PERCENT>              Growing board: adds fields once this share of the board holds stones
+  --grow-step <N>               Columns and rows added each time the board grows [default: 5]
+  --max-size <N>                Width and height the board never grows beyond [default: 1000]
+  --shrink <TURNS>              Shrinking ring: every TURNS turns the outermost open fields turn into walls
+  --spawn-zone <SIZE>           Keeps an empty SIZE by SIZE zone free for every new player for a while
+  --handicap <N>                New players place their first N stones for free, protected from capture for a while
+  --protection <TURNS>          Turns spawn zones and handicap stones stay protected [default: 20]
+  --decay <TURNS>               Stones this old decay unless their group has enough liberties or stones
+  --decay-liberties <N>         Liberties that keep a group from decaying [default: 2]
+  --decay-size <N>              Stones that keep a group from decaying [default: 5]
+  --capture <RULE>              Captured groups: `remove`, go to the `majority` of the capturers, `split` among them
+                                or turn `neutral` [default: remove]
+  --decay-neutral               Decayed stones turn into neutral stones instead of empty fields
+  --convert-neutral             Captured neutral stones go to the player with the most stones next to them
+  --suicide <RULE>              Suicide moves are rejected on the board `before` or `after` the other moves of the
+                                turn, or `allow`ed and removed [default: before]
+  --max-players <N>             Players in a game, further connections wait for a free slot [default: 21044]
+  --max-commands-per-tick <N>   Commands a bot may send per tick, the rest is ignored [default: 16]
+  --max-bytes-per-second <N>    Bytes a bot may send per second before it is throttled, admin and frontend
+                                connections sending more are closed [default: 16384]
+  --max-queries-per-tick <N>    Queries like `GROUP` or `LEGAL` a bot may send per tick [default: 4]
+  --max-invalid-commands <N>    Invalid commands a bot may send before it is kicked [default: 100]
+  --max-connections-per-ip <N>  Bot connections allowed from a single IP address [default: 32]
+  --log <FILTER>                Log filter, e.g. `info,goto::network=debug` [default: $RUST_LOG or `info`]
+  --log-format <FORMAT>         Log output, `text` or `json` [default: text]
+  --help                        Print this help
+";
+
+/// The tick duration can be set anywhere in this range, in milliseconds.
+pub(crate) const TICK_MILLIS: RangeInclusive<u64> = 10..=60_000;
+
+/// Server settings, given on the command line as `--<name> <value>`.
+#[derive(Debug, Clone)]
+pub(crate) struct Config {
+    pub(crate) tick: Duration,
+    /// The admin port is only opened if a token is set.
+    pub(crate) admin_token: Option<String>,
+    pub(crate) admin_port: u16,
+    /// The metrics endpoint is only opened if a port is set.
+    pub(crate) metrics_port: Option<u16>,
+    /// Snapshots are only written if a path is set.
+    pub(crate) snapshot: Option<PathBuf>,
+    pub(crate) snapshot_interval: Duration,
+    pub(crate) resume: bool,
+    /// The radius bots can see around their stones, `None` if they see the whole board.
+    pub(crate) fog: Option<u16>,
+    /// The team of every username in a team, see [`read_teams`].
+    pub(crate) teams: BTreeMap<String, String>,
+    /// `None` lets every connection of a team place a stone per tick.
+    pub(crate) team_moves: Option<usize>,
+    /// The most stones a player can save up, `None` if everybody places a single stone per turn.
+    pub(crate) budget: Option<usize>,
+    pub(crate) capture_bonus: usize,
+    /// The fill level in percent at which the board grows, `None` if it keeps its size.
+    pub(crate) grow: Option<usize>,
+    pub(crate) grow_step: u16,
+    pub(crate) max_size: u16,
+    /// The turns between two rings of walls, `None` if the board doesn't shrink.
+    pub(crate) shrink: Option<u64>,
+    /// The width and height of the spawn zone of a new player, `None` if there are no spawn zones.
+    pub(crate) spawn_zone: Option<u16>,
+    pub(crate) handicap: usize,
+    pub(crate) protection: u32,
+    /// The age at which stones decay, `None` if they never do.
+    pub(crate) decay: Option<u32>,
+    pub(crate) decay_liberties: usize,
+    pub(crate) decay_size: usize,
+    pub(crate) decay_neutral: bool,
+    pub(crate) capture: Capture,
+    pub(crate) convert_neutral: bool,
+    pub(crate) suicide: Suicide,
+    pub(crate) max_players: usize,
+    pub(crate) max_commands_per_tick: usize,
+    pub(crate) max_bytes_per_second: usize,
+    pub(crate) max_queries_per_tick: usize,
+    pub(crate) max_invalid_commands: usize,
+    pub(crate) max_connections_per_ip: usize,
+    /// A `tracing_subscriber::EnvFilter` directive, `None` falls back to `RUST_LOG`.
+    pub(crate) log: Option<String>,
+    pub(crate) log_format: LogFormat,
+}
+
+/// How log events are written to stderr.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum LogFormat {
+    /// Human readable lines.
+    #[default]
+    Text,
+    /// One JSON object per line, for log collectors.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            tick: Duration::from_millis(100),
+            admin_token: None,
+            admin_port: 1314,
+            metrics_port: None,
+            snapshot: None,
+            snapshot_interval: Duration::from_secs(60),
+            resume: false,
+            fog: None,
+            teams: BTreeMap::new(),
+            team_moves: None,
+            budget: None,
+            capture_bonus: 1,
+            grow: None,
+            grow_step: 5,
+            max_size: 1000,
+            shrink: None,
+            spawn_zone: None,
+            handicap: 0,
+            protection: 20,
+            decay: None,
+            decay_liberties: 2,
+            decay_size: 5,
+            decay_neutral: false,
+            capture: Capture::default(),
+            convert_neutral: false,
+            suicide: Suicide::default(),
+            max_players: MAX_PLAYERS,
+            max_commands_per_tick: 16,
+            max_bytes_per_second: 16 * 1024,
+            max_queries_per_tick: 4,
+            max_invalid_commands: 100,
+            max_connections_per_ip: 32,
+            log: None,
+            log_format: LogFormat::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Parses the command line arguments, without the program name.
+    /// Returns `Ok(None)` if the usage was requested.
+    pub(crate) fn from_args(mut args: impl Iterator<Item = String>) -> Result<Option<Self>, String> {
+        let mut config = Config::default();
+        while let Some(flag) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("missing value for {flag}"));
+            match flag.as_str() {
+                "--tick" => {
+                    let millis = parse(&flag, value()?)?;
+                    if !TICK_MILLIS.contains(&millis) {
+                        return Err(format!("{flag} must be between {} and {}", TICK_MILLIS.start(), TICK_MILLIS.end()));
+                    }
+                    config.tick = Duration::from_millis(millis);
+                }
+                "--admin-token" => config.admin_token = Some(value()?),
+                "--admin-port" => config.admin_port = parse(&flag, value()?)?,
+                "--metrics-port" => config.metrics_port = Some(parse(&flag, value()?)?),
+                "--snapshot" => config.snapshot = Some(value()?.into()),
+                "--snapshot-interval" => config.snapshot_interval = Duration::from_secs(parse(&flag, value()?)?),
+                "--resume" => config.resume = true,
+                "--fog" => config.fog = Some(parse(&flag, value()?)?),
+                "--teams" => config.teams = read_teams(&value()?)?,
+                "--team-moves" => config.team_moves = Some(parse(&flag, value()?)?),
+                "--budget" => config.budget = Some(parse(&flag, value()?)?),
+                "--capture-bonus" => config.capture_bonus = parse(&flag, value()?)?,
+                "--grow" => config.grow = Some(parse_positive(&flag, value()?)?),
+                "--grow-step" => config.grow_step = parse(&flag, value()?)?,
+                "--max-size" => config.max_size = parse(&flag, value()?)?,
+                "--shrink" => config.shrink = Some(parse_positive(&flag, value()?)?),
+                "--spawn-zone" => config.spawn_zone = Some(parse_positive(&flag, value()?)?),
+                "--handicap" => config.handicap = parse(&flag, value()?)?,
+                "--protection" => config.protection = parse(&flag, value()?)?,
+                "--decay" => config.decay = Some(parse(&flag, value()?)?),
+                "--decay-liberties" => config.decay_liberties = parse(&flag, value()?)?,
+                "--decay-size" => config.decay_size = parse(&flag, value()?)?,
+                "--capture" => config.capture = parse(&flag, value()?)?,
+                "--decay-neutral" => config.decay_neutral = true,
+                "--convert-neutral" => config.convert_neutral = true,
+                "--suicide" => config.suicide = parse(&flag, value()?)?,
+                "--max-players" => config.max_players = parse(&flag, value()?)?,
+                "--max-commands-per-tick" => config.max_commands_per_tick = parse_positive(&flag, value()?)?,
+                "--max-bytes-per-second" => config.max_bytes_per_second = parse(&flag, value()?)?,
+                "--max-queries-per-tick" => config.max_queries_per_tick = parse(&flag, value()?)?,
+                "--max-invalid-commands" => config.max_invalid_commands = parse(&flag, value()?)?,
+                "--max-connections-per-ip" => config.max_connections_per_ip = parse(&flag, value()?)?,
+                "--log" => config.log = Some(value()?),
+                "--log-format" => config.log_format = parse(&flag, value()?)?,
+                "--help" | "-h" => return Ok(None),
+                _ => return Err(format!("unknown option {flag}")),
+            }
+        }
+        if config.resume && config.snapshot.is_none() {
+            return Err("--resume needs the --snapshot to resume from".to_owned());
+        }
+        Ok(Some(config))
+    }
+}
+
+/// Reads a teams file: one team per line, its name followed by the usernames of its members, separated by spaces.
+fn read_teams(path: &str) -> Result<BTreeMap<String, String>, String> {
+    let teams = std::fs::read_to_string(path).map_err(|e| format!("can't read {path}: {e}"))?;
+    let mut members = BTreeMap::new();
+    for line in teams.lines() {
+        let mut names = line.split_whitespace();
+        let Some(team) = names.next() else { continue };
+        for username in names {
+            if let Some(other) = members.insert(username.to_owned(), team.to_owned()) {
+                return Err(format!("{path}: {username} is in both {other} and {team}"));
+            }
+        }
+    }
+    Ok(members)
+}
+
+fn parse<T: FromStr>(flag: &str, value: String) -> Result<T, String> {
+    value.parse().map_err(|_| format!("invalid value for {flag}: {value}"))
+}
+
+/// Like [`parse`] for options where zero makes no sense.
+fn parse_positive<T: FromStr + Default + PartialEq>(flag: &str, value: String) -> Result<T, String> {
+    let parsed = parse(flag, value)?;
+    if parsed == T::default() {
+        return Err(format!("{flag} must be at least 1"));
+    }
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_args(args: &[&str]) -> Result<Option<Config>, String> {
+        Config::from_args(args.iter().map(|&arg| arg.to_owned()))
+    }
+
+    #[test]
+    fn options_are_parsed() {
+        let config = from_args(&["--grow", "80", "--spawn-zone", "3", "--max-commands-per-tick", "1", "--shrink", "10"])
+            .expect("valid arguments")
+            .expect("no usage requested");
+        assert_eq!((config.grow, config.spawn_zone, config.shrink), (Some(80), Some(3), Some(10)));
+        assert_eq!(config.max_commands_per_tick, 1);
+        assert!(from_args(&["--help"]).expect("valid arguments").is_none());
+    }
+
+    #[test]
+    fn zero_is_rejected_where_it_makes_no_sense() {
+        for flag in ["--max-commands-per-tick", "--grow", "--spawn-zone", "--shrink"] {
+            assert_eq!(from_args(&[flag, "0"]).err(), Some(format!("{flag} must be at least 1")));
+        }
+    }
+
+    #[test]
+    fn invalid_arguments_are_rejected() {
+        assert_eq!(from_args(&["--grow", "-1"]).err(), Some("invalid value for --grow: -1".to_owned()));
+        assert_eq!(from_args(&["--grow"]).err(), Some("missing value for --grow".to_owned()));
+        let tick = format!("--tick must be between {} and {}", TICK_MILLIS.start(), TICK_MILLIS.end());
+        assert_eq!(from_args(&["--tick", "0"]).err(), Some(tick));
+        assert_eq!(from_args(&["--growth"]).err(), Some("unknown option --growth".to_owned()));
+    }
+}
@@ -1,14 +1,87 @@
 use std::{
-    array,
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
     time::{Duration, SystemTime},
 };
 
+pub(crate) use self::capture::Capture;
+pub(crate) use self::decay::Decay;
+pub(crate) use self::encoding::Encoding;
+pub(crate) use self::history::{DeltaState, History};
+pub(crate) use self::query::Query;
+pub(crate) use self::turn::Suicide;
+use self::spawn::Protection;
 use self::uf::UnionFind;
 
 pub(crate) type Position = (u16, u16);
+pub(crate) type PlayerId = u16;
 
+/// Number of players that fit into a game, limited by the characters available to the text encoding.
+pub(crate) const MAX_PLAYERS: usize = 2 * 26 + CJK_IDEOGRAPH_COUNT;
+
+/// Players beyond the latin letters are displayed as CJK Unified Ideographs, which start at U+4E00.
+const CJK_IDEOGRAPHS: u32 = 0x4e00;
+const CJK_IDEOGRAPH_COUNT: usize = 0x5200;
+
+/// The character used for a player by the text encoding:
+/// `A` to `Z`, then `a` to `z` and then CJK ideographs for everyone else.
+pub(crate) fn player_char(id: PlayerId) -> char {
+    let id = u32::from(id);
+    let code = match id {
+        0..=25 => u32::from(b'A') + id,
+        26..=51 => u32::from(b'a') + id - 26,
+        _ => CJK_IDEOGRAPHS + id - 52,
+    };
+    char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER)
+}
+
+/// The player displayed as `c`, the inverse of [`player_char`].
+pub(crate) fn player_id(c: char) -> Option<PlayerId> {
+    let id = match c {
+        'A'..='Z' => u32::from(c) - u32::from('A'),
+        'a'..='z' => u32::from(c) - u32::from('a') + 26,
+        _ => u32::from(c).checked_sub(CJK_IDEOGRAPHS)? + 52,
+    };
+    PlayerId::try_from(id).ok().filter(|&id| usize::from(id) < MAX_PLAYERS)
+}
+
+mod capture;
+mod decay;
+mod encoding;
+mod fog;
+mod history;
+mod query;
+mod snapshot;
+mod spawn;
+mod turn;
 mod uf;
 
+/// Why a move didn't end up on the board.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Rejection {
+    OutOfBounds,
+    /// The field holds a stone or a wall.
+    Occupied,
+    Suicide,
+    /// Another player tried to place a stone on the same field.
+    Contested,
+    /// The field is kept free for another player.
+    Protected,
+}
+
+impl Rejection {
+    /// A short name, used as a metrics label.
+    pub(crate) fn reason(self) -> &'static str {
+        match self {
+            Rejection::OutOfBounds => "out_of_bounds",
+            Rejection::Occupied => "occupied",
+            Rejection::Suicide => "suicide",
+            Rejection::Contested => "contested",
+            Rejection::Protected => "protected",
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub(crate) struct Board {
     tiles: Vec<Tile>,
@@ -16,6 +89,12 @@ pub(crate) struct Board {
     pub(crate) height: u16,
     pub(crate) start: SystemTime,
     uf: UnionFind,
+    /// The protection of every field, see [`Protection`].
+    protection: Vec<Option<Protection>>,
+    /// The fields that have a protection, so protected groups are found without looking at the whole board.
+    protected: BTreeSet<usize>,
+    /// The turns the stone on every field has been there, see [`Board::age_stones`].
+    ages: Vec<u32>,
 }
 
 impl Board {
@@ -26,35 +105,175 @@ impl Board {
             height,
             start: SystemTime::now(),
             uf: UnionFind::new(width as usize, height as usize),
+            protection: vec![None; usize::from(width) * usize::from(height)],
+            protected: BTreeSet::new(),
+            ages: vec![0; usize::from(width) * usize::from(height)],
         }
     }
 
+    /// Builds a board from a map: one line per row, `.` for empty fields, `/` for walls and `#` for neutral stones.
+    pub(crate) fn from_map(map: &str) -> Result<Self, &'static str> {
+        let rows: Vec<&str> = map.lines().map(|row| row.trim_end_matches('\r')).collect();
+        let width = rows.first().map_or(0, |row| row.chars().count());
+        if width == 0 {
+            return Err("the map is empty");
+        }
+        if rows.iter().any(|row| row.chars().count() != width) {
+            return Err("all rows must have the same length");
+        }
+        let (Ok(width), Ok(height)) = (u16::try_from(width), u16::try_from(rows.len())) else {
+            return Err("the map is too big");
+        };
+        let mut board = Board::new(width, height);
+        for (index, c) in rows.iter().flat_map(|row| row.chars()).enumerate() {
+            board.tiles[index] = match c {
+                '.' => Tile::Empty,
+                '/' => Tile::Wall,
+                '#' => Tile::Neutral,
+                _ => return Err("maps may only contain '.', '/' and '#'"),
+            };
+        }
+        // Walls and neutral stones take away a liberty from each neighbor, just like the edge of the board
+        board.rebuild_groups();
+        Ok(board)
+    }
+
     fn index(&self, x: u16, y: u16) -> usize {
         let x = usize::from(x.min(self.width));
         let y = usize::from(y.min(self.height));
         x + usize::from(self.width) * y
     }
 
-    fn is_suicide(&mut self, x: u16, y: u16, id: u8) -> bool {
-        let empty_tiles = self.adjacent_filter(x, y, Tile::Empty);
-        let mut other_players = self.adjacent_tiles(x, y).filter_map(|(_, _, t)| match t {
-            Tile::Player(id) => Some((self.uf.get_liberties(self.index(x, y)), id)),
-            _ => None,
+    /// Whether a stone of player `id` at `(x, y)` would have no liberties and capture nothing.
+    /// Judged on the board before this turn, so other moves of the same turn count as empty fields.
+    /// A move that captures a group isn't suicide, even if the rule of `--capture` doesn't free a liberty for it.
+    fn is_suicide(&mut self, x: u16, y: u16, id: PlayerId) -> bool {
+        // The stones of every neighboring group next to the field, by the root of the group
+        let mut touching = BTreeMap::<usize, (usize, Tile)>::new();
+        for (x, y, tile) in self.adjacent_tiles(x, y).collect::<Vec<_>>() {
+            match tile {
+                Tile::Empty | Tile::TryPlace(_) | Tile::Contested => return false,
+                Tile::Player(_) | Tile::Neutral => {
+                    let root = self.uf.find(self.index(x, y));
+                    touching.entry(root).or_insert((0, tile)).0 += 1;
+                }
+                Tile::Wall => (),
+            }
+        }
+        // The field counts as a liberty once for every stone of a group next to it
+        touching.into_iter().all(|(root, (next, tile))| {
+            let only_liberty = self.uf.get_liberties(root) <= next;
+            if tile == Tile::Player(id) { only_liberty } else { !only_liberty || self.is_protected_group(root) }
+        })
+    }
+
+    /// Renders the board for debugging, one row per line with the liberties of its group after every tile.
+    pub(crate) fn render_liberties(&mut self) -> String {
+        let mut out = String::new();
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let index = self.index(x, y);
+                let _ = write!(out, "{}{:<2}", self.tiles[index].to_char(), self.uf.get_liberties(index));
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    /// The share of the fields that aren't walls which hold a stone, in percent.
+    pub(crate) fn fill_percent(&self) -> usize {
+        let open = self.tiles.iter().filter(|&&tile| tile != Tile::Wall).count();
+        let stones = self.tiles.iter().filter(|tile| matches!(tile, Tile::Player(_))).count();
+        (stones * 100).checked_div(open).unwrap_or(100)
+    }
+
+    /// Adds `step` columns on the right and `step` rows at the bottom, so the coordinates of all fields stay the same.
+    /// The board doesn't grow beyond `max` in either direction. Returns whether it grew.
+    pub(crate) fn grow(&mut self, step: u16, max: u16) -> bool {
+        let width = self.width.saturating_add(step).min(max).max(self.width);
+        let height = self.height.saturating_add(step).min(max).max(self.height);
+        if (width, height) == (self.width, self.height) {
+            return false;
+        }
+        let (old_width, new_width) = (usize::from(self.width), usize::from(width));
+        let mut tiles = vec![Tile::Empty; new_width * usize::from(height)];
+        let mut protection = vec![None; tiles.len()];
+        let mut ages = vec![0; tiles.len()];
+        for row in 0..usize::from(self.height) {
+            let (old, new) = (row * old_width..(row + 1) * old_width, row * new_width..row * new_width + old_width);
+            tiles[new.clone()].copy_from_slice(&self.tiles[old.clone()]);
+            protection[new.clone()].copy_from_slice(&self.protection[old.clone()]);
+            ages[new].copy_from_slice(&self.ages[old]);
+        }
+        self.tiles = tiles;
+        self.protection = protection;
+        self.ages = ages;
+        self.width = width;
+        self.height = height;
+        self.rebuild_groups();
+        true
+    }
+
+    /// Turns the outermost ring of fields that aren't all walls yet into walls.
+    /// Stones on the ring and groups left without liberties are removed.
+    /// Returns the number of removed stones, or `None` if the ring would close the last open fields.
+    pub(crate) fn shrink(&mut self) -> Option<usize> {
+        let (width, height) = (self.width, self.height);
+        let ring = |index: usize| {
+            let (x, y) = ((index % usize::from(width)) as u16, (index / usize::from(width)) as u16);
+            x.min(y).min(width - 1 - x).min(height - 1 - y)
+        };
+        let open = self.tiles.iter().enumerate().filter(|&(_, &tile)| tile != Tile::Wall);
+        let outermost = open.map(|(index, _)| ring(index)).min()?;
+        if 2 * (usize::from(outermost) + 1) >= usize::from(width.min(height)) {
+            return None;
+        }
+        let mut removed = 0;
+        for index in 0..self.tiles.len() {
+            if ring(index) == outermost {
+                removed += usize::from(matches!(self.tiles[index], Tile::Player(_)));
+                self.tiles[index] = Tile::Wall;
+                self.protection[index] = None;
+                self.ages[index] = 0;
+            }
+        }
+        self.rebuild_groups();
+        let mut dead = Vec::new();
+        for index in 0..self.tiles.len() {
+            if matches!(self.tiles[index], Tile::Player(_)) && self.uf.get_liberties(index) == 0 {
+                dead.push(index);
+            }
+        }
+        if !dead.is_empty() {
+            removed += dead.len();
+            for index in dead {
+                self.tiles[index] = Tile::Empty;
+                self.ages[index] = 0;
+            }
+            self.rebuild_groups();
+        }
+        Some(removed)
+    }
+
+    /// Recomputes the groups and their liberties from the tiles, e.g. after the board changed its shape.
+    /// The protected fields are looked up again as well, since their indices may have moved.
+    fn rebuild_groups(&mut self) {
+        let width = usize::from(self.width);
+        self.protected = (0..self.tiles.len()).filter(|&index| self.protection[index].is_some()).collect();
+        let liberties = (0..self.tiles.len()).map(|index| {
+            let (x, y) = ((index % width) as u16, (index / width) as u16);
+            self.adjacent_filter(x, y, Tile::Empty).count()
         });
-        (empty_tiles.count() == 0) && other_players.all(|(libs, oid)| if oid == id { libs < 2 } else { libs > 1 })
-    }
-
-    pub(crate) fn print_board(&mut self) {
-        println!("Board:");
-        for x in 0..self.width {
-            for y in 0..self.height {
-                print!(
-                    "{}{}",
-                    self.tiles[self.index(x, y)].to_char(),
-                    self.uf.get_liberties(self.index(x, y))
-                );
+        self.uf = UnionFind::from_liberties(liberties.collect());
+        for index in 0..self.tiles.len() {
+            let tile = self.tiles[index];
+            if !matches!(tile, Tile::Player(_) | Tile::Neutral) {
+                continue;
+            }
+            let (x, y) = ((index % width) as u16, (index / width) as u16);
+            for (x, y) in self.adjacent_filter(x, y, tile).collect::<Vec<_>>() {
+                self.uf.union(index, self.index(x, y));
             }
-            println!("");
         }
     }
 
@@ -63,11 +282,13 @@ impl Board {
         self.tiles.get_mut(index)
     }
 
-    fn try_tile(&self, x: i16, y: i16) -> Option<(u16, u16, Tile)> {
-        if x < 0 || y < 0 {
+    /// The field at `(x, y)`, if the coordinates exist and are on the board.
+    fn try_tile(&self, x: Option<u16>, y: Option<u16>) -> Option<(u16, u16, Tile)> {
+        let (x, y) = (x?, y?);
+        if x >= self.width || y >= self.height {
             return None;
         }
-        self.tile(x as u16, y as u16).map(|t| (x as u16, y as u16, t))
+        self.tile(x, y).map(|t| (x, y, t))
     }
 
     fn tile(&self, x: u16, y: u16) -> Option<Tile> {
@@ -75,76 +296,102 @@ impl Board {
         self.tiles.get(index).copied()
     }
 
-    pub(crate) fn resolve_conflict(&mut self, x: u16, y: u16) {
-        let index = self.index(x, y);
-        let Some(mut tile) = self.tile(x, y) else { return };
-        tile = match tile {
-            Tile::TryPlace(id) => {
-                self.adjacent_groups(x, y).for_each(|g| self.uf.add_liberty(g, -1));
-                // Unify adjacent player controlled groups into one
-                self.adjacent_filter(x, y, Tile::Player(id))
-                    .for_each(|(x, y)| self.uf.union(index, self.index(x, y)));
-
-                Tile::Player(id)
-            }
-            Tile::Contested => Tile::Empty,
-            t => t,
+    /// Marks a move of player `id` for this turn, it is placed by [`Board::place_stones`].
+    pub(crate) fn try_place(&mut self, x: u16, y: u16, id: PlayerId, suicide: Suicide) -> Result<(), Rejection> {
+        self.check_move(x, y, id, suicide)?;
+        let Some(tile) = self.tile_mut(x, y) else { return Err(Rejection::OutOfBounds) };
+        *tile = match *tile {
+            Tile::Empty => Tile::TryPlace(id),
+            // Teammates placing on the same field agree with each other
+            Tile::TryPlace(other) if other == id => Tile::TryPlace(id),
+            _ => Tile::Contested,
         };
-        self.tiles[index] = tile;
+        Ok(())
     }
 
-    pub(crate) fn try_place(&mut self, x: u16, y: u16, id: u8) {
-        if self.is_suicide(x, y, id) {
-            return;
+    /// Whether player `id` may place a stone on the field, without placing it.
+    fn check_move(&mut self, x: u16, y: u16, id: PlayerId, suicide: Suicide) -> Result<(), Rejection> {
+        if x >= self.width || y >= self.height {
+            return Err(Rejection::OutOfBounds);
         }
-        let Some(tile) = self.tile_mut(x, y) else { return };
-        *tile = match *tile {
-            Tile::Empty => Tile::TryPlace(id),
-            Tile::TryPlace(_) | Tile::Contested => Tile::Contested,
-            t => t,
-        };
+        if matches!(self.tile(x, y), Some(Tile::Wall | Tile::Player(_) | Tile::Neutral)) {
+            return Err(Rejection::Occupied);
+        }
+        if self.is_protected_from(self.index(x, y), id) {
+            return Err(Rejection::Protected);
+        }
+        if suicide == Suicide::Before && self.is_suicide(x, y, id) {
+            return Err(Rejection::Suicide);
+        }
+        Ok(())
     }
 
-    pub(crate) fn kill_neighbors(&mut self, x: u16, y: u16) {
-        for (x, y, _) in self.adjacent_tiles(x, y) {
-            let index = self.index(x, y);
-            if self.uf.get_liberties(index) == 0 {
-                self.remove_group(x, y);
+    /// Whether more than one player tried to place a stone on the field this turn.
+    pub(crate) fn is_contested(&self, x: u16, y: u16) -> bool {
+        self.tile(x, y) == Some(Tile::Contested)
+    }
+
+    /// The number of stones of every player on the board.
+    pub(crate) fn stones(&self) -> BTreeMap<PlayerId, usize> {
+        let mut stones = BTreeMap::new();
+        for tile in &self.tiles {
+            if let Tile::Player(id) = tile {
+                *stones.entry(*id).or_default() += 1;
             }
         }
+        stones
     }
 
     pub(crate) fn serialize(&self) -> String {
-        self.tiles.iter().map(Tile::to_char).collect()
-    }
-
-    fn remove_group(&mut self, x: u16, y: u16) {
-        dbg!("removing tiles", x, y);
-        let tile = *self.tile_mut(x, y).expect("Tried to remove non board space");
-        let mut stack = vec![(x, y, tile)];
-        while let Some((x, y, t)) = stack.pop() {
-            if t == tile {
-                self.uf.reset_node(self.index(x, y));
-                self.adjacent_tiles(x, y).for_each(|d| stack.push(d));
-                self.adjacent_groups(x, y).for_each(|g| self.uf.add_liberty(g, 1));
-                *self.tile_mut(x, y).unwrap() = Tile::Empty;
+        self.tiles.iter().copied().map(Tile::to_char).collect()
+    }
+
+    /// Serializes the given tiles as space separated `<x> <y> <tile>` triples.
+    /// The tile is its character or, for the binary encoding, its numeric code.
+    pub(crate) fn serialize_changes(&self, indices: &[usize], encoding: Encoding) -> String {
+        let width = usize::from(self.width);
+        indices
+            .iter()
+            .map(|&index| {
+                let (x, y, tile) = (index % width, index / width, self.tiles[index]);
+                match encoding {
+                    Encoding::Text => format!("{x} {y} {}", tile.to_char()),
+                    Encoding::Binary => format!("{x} {y} {}", tile.code()),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Clears the group at the given position and returns the number of removed stones.
+    fn remove_group(&mut self, x: u16, y: u16) -> usize {
+        tracing::debug!(x, y, "removing group");
+        let group = self.group(x, y);
+        for &(x, y) in &group {
+            let index = self.index(x, y);
+            self.tiles[index] = Tile::Empty;
+            self.ages[index] = 0;
+        }
+        // Every stone next to a removed one gains the field as a liberty
+        for &(x, y) in &group {
+            for (x, y, tile) in self.adjacent_tiles(x, y).collect::<Vec<_>>() {
+                if matches!(tile, Tile::Player(_) | Tile::Neutral) {
+                    let root = self.uf.find(self.index(x, y));
+                    self.uf.add_liberty(root, 1);
+                }
             }
         }
+        for &(x, y) in &group {
+            let liberties = self.adjacent_filter(x, y, Tile::Empty).count();
+            self.uf.reset_node(self.index(x, y), liberties);
+        }
+        group.len()
     }
 
     fn adjacent_filter(&self, x: u16, y: u16, tile: Tile) -> impl Iterator<Item = (u16, u16)> {
         self.adjacent_tiles(x, y)
-            .filter_map(move |(x, y, t)| (t == tile).then(|| (x, y)))
-    }
-
-    fn adjacent_groups(&mut self, x: u16, y: u16) -> impl Iterator<Item = usize> {
-        let mut iter = self.adjacent_tiles(x, y);
-        let mut tiles: [_; 4] = array::from_fn(|_| iter.next().map(|(x, y, _)| self.uf.find(self.index(x, y))));
-
-        tiles.sort();
-        dedup(&mut tiles);
-
-        tiles.into_iter().flatten()
+            .filter(move |&(_, _, t)| t == tile)
+            .map(|(x, y, _)| (x, y))
     }
 
     pub(crate) fn reset_timer(&mut self) {
@@ -159,12 +406,11 @@ impl Board {
     }
 
     fn adjacent_tiles(&self, x: u16, y: u16) -> impl Iterator<Item = (u16, u16, Tile)> {
-        let (x, y) = (x as i16, y as i16);
         let data = [
-            self.try_tile(x - 1, y),
-            self.try_tile(x, y - 1),
-            self.try_tile(x + 1, y),
-            self.try_tile(x, y + 1),
+            self.try_tile(x.checked_sub(1), Some(y)),
+            self.try_tile(Some(x), y.checked_sub(1)),
+            self.try_tile(x.checked_add(1), Some(y)),
+            self.try_tile(Some(x), y.checked_add(1)),
         ];
         data.into_iter().flatten()
     }
@@ -175,29 +421,123 @@ enum Tile {
     #[default]
     Empty,
     Wall,
-    Player(u8),
-    TryPlace(u8),
+    Player(PlayerId),
+    /// A stone that belongs to nobody. It can be captured like any other stone.
+    Neutral,
+    TryPlace(PlayerId),
     Contested,
 }
 
 impl Tile {
-    fn to_char(&self) -> char {
+    fn to_char(self) -> char {
         match self {
             Tile::Empty => '.',
             Tile::Wall => '/',
-            Tile::Player(c) => *c as char,
+            Tile::Neutral => '#',
+            Tile::Player(id) => player_char(id),
             _ => unreachable!("Forgot to clean up intermediate board state"),
         }
     }
+
+    fn from_char(c: char) -> Option<Tile> {
+        match c {
+            '.' => Some(Tile::Empty),
+            '/' => Some(Tile::Wall),
+            '#' => Some(Tile::Neutral),
+            c => player_id(c).map(Tile::Player),
+        }
+    }
 }
 
-fn dedup<const N: usize, T: Eq>(array: &mut [Option<T>; N]) {
-    let mut last_unique = 0;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn player_chars_round_trip() {
+        for id in 0..MAX_PLAYERS as PlayerId {
+            assert_eq!(player_id(player_char(id)), Some(id), "{id}");
+        }
+    }
+
+    #[test]
+    fn player_chars_switch_alphabets_at_the_boundaries() {
+        let chars = [0, 25, 26, 51, 52, MAX_PLAYERS as PlayerId - 1].map(player_char);
+        assert_eq!(chars, ['A', 'Z', 'a', 'z', '\u{4e00}', '\u{9fff}']);
+    }
+
+    #[test]
+    fn other_chars_are_no_players() {
+        for c in ['.', '/', '#', '?', '-', '0', '@', '[', '`', '{', '\u{4dff}', '\u{a000}', '\u{1f600}'] {
+            assert_eq!(player_id(c), None, "{c}");
+        }
+    }
 
-    for next_candidate in 1..N {
-        if array[last_unique] == array[next_candidate] {
-            array[last_unique] = None;
+    fn board(rows: &[&str]) -> Board {
+        let mut board = Board::new(rows[0].len() as u16, rows.len() as u16);
+        for (index, c) in rows.concat().chars().enumerate() {
+            board.tiles[index] = Tile::from_char(c).expect("valid tile");
         }
-        last_unique = next_candidate;
+        board.rebuild_groups();
+        board
+    }
+
+    #[test]
+    fn growing_keeps_the_coordinates() {
+        let mut board = board(["A/", ".B"].as_slice());
+        assert!(board.grow(2, 10));
+        assert_eq!((board.width, board.height), (4, 4));
+        assert_eq!(board.serialize(), "A/...B..........");
+        assert_eq!(board.uf.get_liberties(board.index(1, 1)), 3);
+    }
+
+    #[test]
+    fn growing_stops_at_the_maximum_size() {
+        let mut board = Board::new(8, 3);
+        assert!(board.grow(4, 9));
+        assert_eq!((board.width, board.height), (9, 7));
+        assert!(board.grow(4, 9));
+        assert_eq!((board.width, board.height), (9, 9));
+        assert!(!board.grow(4, 9));
+        // Sides already beyond the maximum stay as they are
+        let mut board = Board::new(u16::MAX, 1);
+        assert!(board.grow(u16::MAX, 2));
+        assert_eq!((board.width, board.height), (u16::MAX, 2));
+        assert!(!board.grow(u16::MAX, 2));
+    }
+
+    #[test]
+    fn shrinking_walls_the_outermost_ring() {
+        let mut outer = board(["A....", ".....", ".AB..", ".....", "....."].as_slice());
+        assert_eq!(outer.shrink(), Some(1));
+        assert_eq!(outer.serialize(), "//////...//AB.//...//////");
+        // Stones left without liberties are removed as well
+        let mut enclosed = board(["//.//", "//A//", "//B//", "/.../", "/////"].as_slice());
+        assert_eq!(enclosed.shrink(), Some(1));
+        assert_eq!(enclosed.serialize(), "///////.////B///...//////");
+    }
+
+    #[test]
+    fn shrinking_stops_before_the_center() {
+        let mut open = Board::new(6, 6);
+        assert_eq!(open.shrink(), Some(0));
+        assert_eq!(open.shrink(), Some(0));
+        assert_eq!(open.shrink(), None);
+        assert_eq!(open.serialize().matches('.').count(), 4);
+        assert_eq!(Board::new(u16::MAX, 1).shrink(), None);
+        assert_eq!(board(["///", "///"].as_slice()).shrink(), None);
+    }
+
+    #[test]
+    fn neighbors_are_found_on_the_widest_boards() {
+        let mut board = Board::new(u16::MAX, 2);
+        board.tiles[32767] = Tile::Wall;
+        board.tiles[32769] = Tile::Neutral;
+        let neighbors: Vec<_> = board.adjacent_tiles(32768, 0).collect();
+        assert_eq!(neighbors, [(32767, 0, Tile::Wall), (32769, 0, Tile::Neutral), (32768, 1, Tile::Empty)]);
+        let corner: Vec<_> = board.adjacent_tiles(u16::MAX - 1, 1).map(|(x, y, _)| (x, y)).collect();
+        assert_eq!(corner, [(u16::MAX - 2, 1), (u16::MAX - 1, 0)]);
+        let origin: Vec<_> = board.adjacent_tiles(0, 0).map(|(x, y, _)| (x, y)).collect();
+        assert_eq!(origin, [(1, 0), (0, 1)]);
     }
 }
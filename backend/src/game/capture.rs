@@ -0,0 +1,180 @@
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, VecDeque};
+use std::str::FromStr;
+
+use super::{Board, PlayerId, Position, Tile};
+
+/// What happens to a captured group of player stones.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum Capture {
+    /// The group is removed, like in Go.
+    #[default]
+    Remove,
+    /// The group goes to the player with the most stones next to it, or turns neutral on a tie.
+    Majority,
+    /// Every stone goes to the player with the most stones next to it, or turns neutral on a tie.
+    /// Stones without a capturing neighbor go the same way as the closest stone that has one.
+    Split,
+    /// The group turns into neutral stones.
+    Neutral,
+}
+
+impl FromStr for Capture {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "remove" => Ok(Capture::Remove),
+            "majority" => Ok(Capture::Majority),
+            "split" => Ok(Capture::Split),
+            "neutral" => Ok(Capture::Neutral),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Board {
+    /// The positions of all stones in the group at the given position.
+    pub(crate) fn group(&self, x: u16, y: u16) -> Vec<Position> {
+        let Some(tile) = self.tile(x, y) else { return Vec::new() };
+        let mut seen = vec![false; self.tiles.len()];
+        let mut group = Vec::new();
+        let mut stack = vec![(x, y)];
+        while let Some((x, y)) = stack.pop() {
+            let index = self.index(x, y);
+            if self.tiles[index] == tile && !seen[index] {
+                seen[index] = true;
+                group.push((x, y));
+                stack.extend(self.adjacent_filter(x, y, tile));
+            }
+        }
+        group
+    }
+
+    /// What every stone of a captured group turns into, without changing the board yet.
+    /// Player groups follow `capture`. Neutral groups are removed, or with `convert_neutral` go to the player with the
+    /// most stones next to them, unless that is a tie.
+    pub(super) fn capture_targets(&self, group: &[Position], capture: Capture, convert_neutral: bool) -> Vec<Tile> {
+        let Some(&(x, y)) = group.first() else { return Vec::new() };
+        let captured = self.tiles[self.index(x, y)];
+        let whole = |tile| vec![tile; group.len()];
+        if captured == Tile::Neutral {
+            return match self.leaders(group, captured)[..] {
+                [id] if convert_neutral => whole(Tile::Player(id)),
+                _ => whole(Tile::Empty),
+            };
+        }
+        match capture {
+            Capture::Remove => whole(Tile::Empty),
+            Capture::Majority => match self.leaders(group, captured)[..] {
+                [] => whole(Tile::Empty),
+                [id] => whole(Tile::Player(id)),
+                _ => whole(Tile::Neutral),
+            },
+            Capture::Split => self.split_targets(group, captured),
+            Capture::Neutral => whole(Tile::Neutral),
+        }
+    }
+
+    /// Hands out the stones of a captured group one by one, see [`Capture::Split`].
+    fn split_targets(&self, group: &[Position], captured: Tile) -> Vec<Tile> {
+        let mut owners = BTreeMap::new();
+        let mut queue = VecDeque::new();
+        for &(x, y) in group {
+            let owner = match self.leaders(&[(x, y)], captured)[..] {
+                [] => continue,
+                [id] => Tile::Player(id),
+                _ => Tile::Neutral,
+            };
+            owners.insert((x, y), owner);
+            queue.push_back((x, y));
+        }
+        while let Some((x, y)) = queue.pop_front() {
+            let owner = owners[&(x, y)];
+            for (x, y) in self.adjacent_filter(x, y, captured).collect::<Vec<_>>() {
+                if let Entry::Vacant(entry) = owners.entry((x, y)) {
+                    entry.insert(owner);
+                    queue.push_back((x, y));
+                }
+            }
+        }
+        // Without any capturing neighbor the group is simply removed
+        group.iter().map(|position| owners.get(position).copied().unwrap_or(Tile::Empty)).collect()
+    }
+
+    /// The players with the most stones next to the given stones, apart from the owner of the `captured` stones.
+    fn leaders(&self, stones: &[Position], captured: Tile) -> Vec<PlayerId> {
+        let mut neighbors: Vec<_> = stones.iter().flat_map(|&(x, y)| self.adjacent_tiles(x, y)).collect();
+        neighbors.sort_unstable_by_key(|&(x, y, _)| (x, y));
+        neighbors.dedup();
+        let mut counts = BTreeMap::<PlayerId, usize>::new();
+        for (_, _, tile) in neighbors {
+            if let Tile::Player(id) = tile {
+                if tile != captured {
+                    *counts.entry(id).or_default() += 1;
+                }
+            }
+        }
+        let most = counts.values().copied().max().unwrap_or_default();
+        counts.into_iter().filter(|&(_, count)| count == most).map(|(id, _)| id).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn board(rows: &[&str]) -> Board {
+        let mut board = Board::new(rows[0].len() as u16, rows.len() as u16);
+        for (index, c) in rows.concat().chars().enumerate() {
+            board.tiles[index] = Tile::from_char(c).expect("valid tile");
+        }
+        board.rebuild_groups();
+        board
+    }
+
+    /// What the group at `(x, y)` turns into, stone by stone, sorted by position.
+    fn targets(rows: &[&str], (x, y): Position, capture: Capture, convert_neutral: bool) -> Vec<(Position, char)> {
+        let board = board(rows);
+        let group = board.group(x, y);
+        let targets = board.capture_targets(&group, capture, convert_neutral);
+        let mut targets: Vec<_> = group.into_iter().zip(targets.into_iter().map(Tile::to_char)).collect();
+        targets.sort_unstable();
+        targets
+    }
+
+    #[test]
+    fn majority_goes_to_the_player_with_most_neighbors() {
+        assert_eq!(targets(&["/A/", "ABC", "/./"], (1, 1), Capture::Majority, false), [((1, 1), 'A')]);
+        assert_eq!(targets(&["/A/", "/BC", "///"], (1, 1), Capture::Majority, false), [((1, 1), '#')]);
+        assert_eq!(targets(&["///", "/B/", "///"], (1, 1), Capture::Majority, false), [((1, 1), '.')]);
+        assert_eq!(targets(&["/A/", "ABC", "/./"], (1, 1), Capture::Remove, false), [((1, 1), '.')]);
+        assert_eq!(targets(&["/A/", "ABC", "/./"], (1, 1), Capture::Neutral, false), [((1, 1), '#')]);
+    }
+
+    #[test]
+    fn neighbors_of_several_stones_count_once() {
+        // A touches two stones of the group, C only one, which is still a tie
+        let targets = targets(&["AB/", "BBC", "//."], (1, 1), Capture::Majority, false);
+        assert_eq!(targets, [((0, 1), '#'), ((1, 0), '#'), ((1, 1), '#')]);
+    }
+
+    #[test]
+    fn split_hands_out_every_stone() {
+        let split = targets(&["ACC", "BBB", "/A/"], (0, 1), Capture::Split, false);
+        assert_eq!(split, [((0, 1), 'A'), ((1, 1), '#'), ((2, 1), 'C')]);
+        let split = targets(&["A...", "BBBB"], (0, 1), Capture::Split, false);
+        assert_eq!(split, [((0, 1), 'A'), ((1, 1), 'A'), ((2, 1), 'A'), ((3, 1), 'A')]);
+        let split = targets(&["BB", "//"], (0, 0), Capture::Split, false);
+        assert_eq!(split, [((0, 0), '.'), ((1, 0), '.')]);
+    }
+
+    #[test]
+    fn neutral_groups_are_removed_or_converted() {
+        let rows = ["A#.", "A#C"];
+        assert_eq!(targets(&rows, (1, 0), Capture::Majority, false), [((1, 0), '.'), ((1, 1), '.')]);
+        assert_eq!(targets(&rows, (1, 0), Capture::Majority, true), [((1, 0), 'A'), ((1, 1), 'A')]);
+        assert_eq!(targets(&rows, (1, 0), Capture::Neutral, true), [((1, 0), 'A'), ((1, 1), 'A')]);
+        assert_eq!(targets(&["A#C"], (1, 0), Capture::Majority, true), [((1, 0), '.')]);
+    }
+}
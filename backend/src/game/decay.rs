@@ -0,0 +1,145 @@
+use super::fog::View;
+use super::{Board, Tile};
+
+/// When old stones decay, see [`Board::decay`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Decay {
+    /// The age in turns at which a stone starts to decay.
+    pub(crate) after: u32,
+    /// Groups with at least this many liberties are safe.
+    pub(crate) liberties: usize,
+    /// Groups with at least this many stones are safe.
+    pub(crate) size: usize,
+    /// Whether decayed stones turn into neutral stones instead of empty fields.
+    pub(crate) neutral: bool,
+}
+
+impl Board {
+    /// Ages every stone by a turn. Fields without a stone have age 0.
+    pub(crate) fn age_stones(&mut self) {
+        for (age, tile) in self.ages.iter_mut().zip(&self.tiles) {
+            *age = if matches!(tile, Tile::Player(_)) { age.saturating_add(1) } else { 0 };
+        }
+    }
+
+    /// Removes the stones old enough to decay that belong to a group with too few liberties and stones,
+    /// or turns them neutral. Protected stones don't decay. Returns the number of decayed stones.
+    pub(crate) fn decay(&mut self, decay: Decay) -> usize {
+        let mut decayed = Vec::new();
+        for index in 0..self.tiles.len() {
+            if !matches!(self.tiles[index], Tile::Player(_))
+                || self.ages[index] < decay.after
+                || self.protection[index].is_some()
+            {
+                continue;
+            }
+            if self.uf.get_liberties(index) < decay.liberties && self.uf.get_size(index) < decay.size {
+                decayed.push(index);
+            }
+        }
+        for &index in &decayed {
+            self.tiles[index] = if decay.neutral { Tile::Neutral } else { Tile::Empty };
+            self.ages[index] = 0;
+        }
+        if !decayed.is_empty() {
+            self.rebuild_groups();
+        }
+        decayed.len()
+    }
+
+    /// Serializes the age of every field as space separated numbers.
+    pub(crate) fn serialize_ages(&self) -> String {
+        self.ages.iter().map(u32::to_string).collect::<Vec<_>>().join(" ")
+    }
+}
+
+impl View<'_> {
+    /// Serializes the ages like [`Board::serialize_ages`], with 0 for hidden fields.
+    pub(crate) fn serialize_ages(&self) -> String {
+        let ages = self.tiles().zip(&self.board.ages).map(|(tile, &age)| if tile.is_some() { age } else { 0 });
+        ages.map(|age| age.to_string()).collect::<Vec<_>>().join(" ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{Capture, Suicide};
+
+    const DECAY: Decay = Decay { after: 2, liberties: 2, size: 2, neutral: false };
+
+    /// A board whose stones have all been there for `turns` turns.
+    fn aged(rows: &[&str], turns: u32) -> Board {
+        let mut board = Board::new(rows[0].len() as u16, rows.len() as u16);
+        for (index, c) in rows.concat().chars().enumerate() {
+            board.tiles[index] = Tile::from_char(c).expect("valid tile");
+        }
+        board.rebuild_groups();
+        for _ in 0..turns {
+            board.age_stones();
+        }
+        board
+    }
+
+    #[test]
+    fn only_stones_age() {
+        let mut board = aged(&["A.", "#B"], 2);
+        assert_eq!(board.serialize_ages(), "2 0 0 2");
+        board.tiles[0] = Tile::Empty;
+        board.age_stones();
+        assert_eq!(board.serialize_ages(), "0 0 0 3");
+    }
+
+    #[test]
+    fn old_groups_decay_with_few_liberties_and_few_stones() {
+        // A has no liberties, B has two stones, C has two liberties and E is too young
+        let rows = ["A/BB/", "//.//", "E..C."];
+        let mut board = aged(&rows, 2);
+        board.ages[10] = 1;
+        assert_eq!(board.decay(DECAY), 1);
+        assert_eq!(board.serialize(), "./BB///.//E..C.");
+        assert_eq!(board.serialize_ages(), "0 0 2 2 0 0 0 0 0 0 1 0 0 2 0");
+
+        let mut board = aged(&rows, 2);
+        assert_eq!(board.decay(Decay { neutral: true, ..DECAY }), 2);
+        assert_eq!(board.serialize(), "#/BB///.//#..C.");
+        assert_eq!(board.serialize_ages(), "0 0 2 2 0 0 0 0 0 0 0 0 0 2 0");
+    }
+
+    #[test]
+    fn protected_stones_do_not_decay() {
+        let mut board = aged(&["A.", "/."], 5);
+        board.protect(0, 0, 0, 1);
+        assert_eq!(board.decay(DECAY), 0);
+        assert_eq!(board.expire_protection(), 0);
+        assert_eq!(board.decay(DECAY), 1);
+    }
+
+    #[test]
+    fn ages_stay_with_their_stones_when_the_board_grows() {
+        let mut board = aged(&["A.", ".B"], 2);
+        assert!(board.grow(1, 10));
+        assert_eq!(board.serialize_ages(), "2 0 0 0 2 0 0 0 0");
+    }
+
+    #[test]
+    fn removed_stones_lose_their_age_when_the_board_shrinks() {
+        // The stone in the corner ends up under a wall, the one next to it loses its last liberties
+        let mut board = aged(&["A....", ".AB..", ".B...", ".....", "....."], 2);
+        assert_eq!(board.shrink(), Some(2));
+        let ages: Vec<_> = board.serialize_ages().split(' ').map(str::to_owned).collect();
+        let old: Vec<_> = ages.iter().enumerate().filter(|(_, age)| *age != "0").map(|(index, _)| index).collect();
+        assert_eq!(old, [7, 11]);
+    }
+
+    #[test]
+    fn captured_and_new_stones_start_at_age_0() {
+        let mut board = aged(&["BA.", "...", "..."], 3);
+        board.try_place(0, 1, 0, Suicide::Before).expect("legal move");
+        let placed = board.place_stones(&[(0, 0, 1)]);
+        assert_eq!(board.capture(&placed, Capture::Remove, false).stones, 1);
+        assert_eq!(board.serialize_ages(), "0 3 0 0 0 0 0 0 0");
+        board.age_stones();
+        assert_eq!(board.serialize_ages(), "0 4 0 1 0 0 0 0 0");
+    }
+}
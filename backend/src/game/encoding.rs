@@ -0,0 +1,168 @@
+use super::fog::View;
+use super::{Board, Tile};
+
+/// The code of a field hidden by the fog of war.
+const UNSEEN_CODE: u32 = u32::MAX;
+/// The code of a neutral stone, out of the way of the player codes.
+const NEUTRAL_CODE: u32 = u32::MAX - 1;
+
+/// How boards are put on the wire for a connection.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum Encoding {
+    /// One character per tile, see [`Board::serialize`].
+    #[default]
+    Text,
+    /// Run-length encoded tile codes, see [`Board::serialize_binary`].
+    Binary,
+}
+
+impl Tile {
+    /// The numeric code of a tile used by the binary encoding.
+    pub(super) fn code(self) -> u32 {
+        match self {
+            Tile::Empty => 0,
+            Tile::Wall => 1,
+            Tile::Player(id) => u32::from(id) + 2,
+            Tile::Neutral => NEUTRAL_CODE,
+            _ => unreachable!("Forgot to clean up intermediate board state"),
+        }
+    }
+}
+
+/// Appends `value` as an unsigned LEB128 varint.
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+impl Board {
+    /// Serializes the board in the binary encoding described in the README:
+    /// width and height as little endian `u16`, followed by `<tile code> <run length>` varint pairs.
+    pub(crate) fn serialize_binary(&self) -> Vec<u8> {
+        encode_binary(self.width, self.height, self.tiles.iter().copied().map(Tile::code))
+    }
+}
+
+impl View<'_> {
+    /// Serializes the view like [`Board::serialize_binary`], with [`UNSEEN_CODE`] for hidden fields.
+    pub(crate) fn serialize_binary(&self) -> Vec<u8> {
+        let codes = self.tiles().map(|tile| tile.map_or(UNSEEN_CODE, Tile::code));
+        encode_binary(self.board.width, self.board.height, codes)
+    }
+}
+
+fn encode_binary(width: u16, height: u16, codes: impl Iterator<Item = u32>) -> Vec<u8> {
+    let mut out = Vec::with_capacity(16);
+    out.extend_from_slice(&width.to_le_bytes());
+    out.extend_from_slice(&height.to_le_bytes());
+    let mut codes = codes.peekable();
+    while let Some(code) = codes.next() {
+        let mut run = 1;
+        while codes.next_if_eq(&code).is_some() {
+            run += 1;
+        }
+        write_varint(&mut out, u64::from(code));
+        write_varint(&mut out, run);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::PlayerId;
+
+    fn read_varint(bytes: &mut impl Iterator<Item = u8>) -> Option<u64> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = bytes.next()?;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    /// Decodes a binary board into its dimensions and tile codes.
+    fn deserialize_binary(bytes: &[u8]) -> Option<(u16, u16, Vec<u32>)> {
+        let width = u16::from_le_bytes(bytes.get(0..2)?.try_into().ok()?);
+        let height = u16::from_le_bytes(bytes.get(2..4)?.try_into().ok()?);
+        let len = usize::from(width) * usize::from(height);
+        let mut bytes = bytes[4..].iter().copied();
+        let mut tiles = Vec::with_capacity(len);
+        while tiles.len() < len {
+            let code = u32::try_from(read_varint(&mut bytes)?).ok()?;
+            let run = usize::try_from(read_varint(&mut bytes)?).ok()?;
+            if run == 0 || tiles.len() + run > len {
+                return None;
+            }
+            tiles.extend(std::iter::repeat_n(code, run));
+        }
+        bytes.next().is_none().then_some((width, height, tiles))
+    }
+
+    fn assert_round_trip(board: &Board) {
+        let (width, height, tiles) = deserialize_binary(&board.serialize_binary()).expect("invalid encoding");
+        assert_eq!((width, height), (board.width, board.height));
+        assert_eq!(tiles, board.tiles.iter().copied().map(Tile::code).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn varint_round_trip() {
+        for value in [0, 1, 127, 128, 300, 16_383, 16_384, u64::from(u32::MAX), u64::MAX] {
+            let mut out = Vec::new();
+            write_varint(&mut out, value);
+            assert_eq!(read_varint(&mut out.into_iter()), Some(value));
+        }
+    }
+
+    #[test]
+    fn empty_board_is_a_single_run() {
+        let board = Board::new(19, 19);
+        assert_eq!(board.serialize_binary(), [19, 0, 19, 0, 0, 0xe9, 0x02]);
+        assert_round_trip(&board);
+    }
+
+    #[test]
+    fn mixed_board_round_trip() {
+        let mut board = Board::new(7, 5);
+        for (i, tile) in board.tiles.iter_mut().enumerate() {
+            *tile = match i % 5 {
+                0 | 1 => Tile::Empty,
+                2 => Tile::Wall,
+                _ => Tile::Player((i % 3) as PlayerId),
+            };
+        }
+        assert_round_trip(&board);
+    }
+
+    #[test]
+    fn widest_board_round_trip() {
+        let mut board = Board::new(u16::MAX, 2);
+        board.tiles[usize::from(u16::MAX) - 1] = Tile::Player(1000);
+        board.tiles[usize::from(u16::MAX)] = Tile::Wall;
+        assert_round_trip(&board);
+    }
+
+    #[test]
+    fn hidden_fields_have_their_own_code() {
+        let mut board = Board::new(9, 9);
+        *board.tile_mut(1, 1).unwrap() = Tile::Player(0);
+        *board.tile_mut(7, 7).unwrap() = Tile::Player(1);
+        let (_, _, codes) = deserialize_binary(&board.view(Some(0), 2).serialize_binary()).expect("invalid encoding");
+        assert_eq!(codes[board.index(1, 1)], Tile::Player(0).code());
+        assert_eq!(codes[board.index(7, 7)], UNSEEN_CODE);
+    }
+
+    #[test]
+    fn truncated_input_is_rejected() {
+        let mut board = Board::new(4, 4);
+        board.tiles[5] = Tile::Player(1);
+        let bytes = board.serialize_binary();
+        assert!(deserialize_binary(&bytes[..bytes.len() - 1]).is_none());
+    }
+}
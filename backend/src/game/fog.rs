@@ -0,0 +1,101 @@
+use super::{Board, PlayerId, Tile};
+
+/// The character of a field hidden by the fog of war.
+pub(super) const UNSEEN: char = '?';
+
+/// The board as a single player sees it in fog of war mode, see [`Board::view`].
+pub(crate) struct View<'a> {
+    pub(super) board: &'a Board,
+    visible: Vec<bool>,
+}
+
+impl Board {
+    /// What player `id` sees if it can look `radius` fields far from each of its stones, measured as the crow flies.
+    /// Connections without a player see nothing.
+    pub(crate) fn view(&self, id: Option<PlayerId>, radius: u16) -> View<'_> {
+        View { board: self, visible: self.visible(id, radius) }
+    }
+
+    /// Which fields player `id` sees, see [`Board::view`].
+    pub(crate) fn visible(&self, id: Option<PlayerId>, radius: u16) -> Vec<bool> {
+        let mut visible = vec![false; self.tiles.len()];
+        let (width, height, radius) = (i64::from(self.width), i64::from(self.height), i64::from(radius));
+        let stones = self.tiles.iter().enumerate().filter(|&(_, &tile)| id.is_some_and(|id| tile == Tile::Player(id)));
+        for (index, _) in stones {
+            let (x, y) = (index as i64 % width, index as i64 / width);
+            for row in (y - radius).max(0)..=(y + radius).min(height - 1) {
+                let reach = (radius * radius - (row - y) * (row - y)).isqrt();
+                let start = (row * width + (x - reach).max(0)) as usize;
+                let end = (row * width + (x + reach).min(width - 1)) as usize;
+                visible[start..=end].fill(true);
+            }
+        }
+        visible
+    }
+}
+
+impl View<'_> {
+    /// The fields of the board in order, `None` for hidden ones.
+    pub(super) fn tiles(&self) -> impl Iterator<Item = Option<Tile>> + '_ {
+        self.board.tiles.iter().zip(&self.visible).map(|(&tile, &visible)| visible.then_some(tile))
+    }
+
+    /// Serializes the view like [`Board::serialize`], with `?` for hidden fields.
+    pub(crate) fn serialize(&self) -> String {
+        self.tiles().map(|tile| tile.map_or(UNSEEN, Tile::to_char)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 9x9 board with a stone of player 0 at 1 1 and one of player 1 at 7 7.
+    fn board() -> Board {
+        let mut board = Board::new(9, 9);
+        *board.tile_mut(1, 1).unwrap() = Tile::Player(0);
+        *board.tile_mut(7, 7).unwrap() = Tile::Player(1);
+        board
+    }
+
+    #[test]
+    fn fog_hides_distant_fields() {
+        let board = board();
+        let visible = board.visible(Some(0), 2);
+        let seen = |x: u16, y: u16| visible[board.index(x, y)];
+        assert!(seen(1, 1) && seen(3, 1) && seen(0, 0) && seen(2, 2));
+        assert!(!seen(3, 3) && !seen(4, 1) && !seen(7, 7));
+    }
+
+    #[test]
+    fn views_show_only_visible_fields() {
+        let board = board();
+        let view = board.view(Some(1), 1).serialize().chars().collect::<Vec<_>>();
+        assert_eq!(view[board.index(7, 7)], 'B');
+        assert_eq!(view[board.index(7, 6)], '.');
+        assert_eq!(view[board.index(1, 1)], UNSEEN);
+        assert_eq!(view.iter().filter(|&&tile| tile != UNSEEN).count(), 5);
+    }
+
+    #[test]
+    fn radius_zero_sees_only_own_stones() {
+        let board = board();
+        let visible = board.visible(Some(0), 0);
+        assert_eq!(visible.iter().filter(|&&seen| seen).count(), 1);
+        assert!(visible[board.index(1, 1)]);
+    }
+
+    #[test]
+    fn large_radii_stop_at_the_edges() {
+        let board = board();
+        assert!(board.visible(Some(1), u16::MAX).iter().all(|&seen| seen));
+        assert_eq!(board.visible(Some(0), 12).iter().filter(|&&seen| seen).count(), 81);
+    }
+
+    #[test]
+    fn spectating_connections_see_nothing() {
+        let board = board();
+        assert!(board.visible(None, 10).iter().all(|&seen| !seen));
+        assert_eq!(board.view(None, 10).serialize(), "?".repeat(81));
+    }
+}
@@ -0,0 +1,193 @@
+use std::collections::VecDeque;
+
+use super::snapshot::parse_tiles;
+use super::{Board, Tile};
+use crate::snapshot::{value, values, Fields};
+
+/// Every `KEYFRAME_INTERVAL` turns all clients receive the full board, even if they asked for deltas.
+pub(crate) const KEYFRAME_INTERVAL: u64 = 50;
+
+/// Remembers which tiles changed during the last turns, so clients can be sent deltas instead of the full board.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct History {
+    turn: u64,
+    previous: Vec<Tile>,
+    changes: VecDeque<Vec<usize>>,
+}
+
+/// Per connection state of the delta encoding.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct DeltaState {
+    pub(crate) enabled: bool,
+    pub(crate) acked_turn: Option<u64>,
+}
+
+impl History {
+    pub(crate) fn new(board: &Board) -> Self {
+        History {
+            turn: 0,
+            previous: board.tiles.clone(),
+            changes: VecDeque::new(),
+        }
+    }
+
+    pub(crate) fn turn(&self) -> u64 {
+        self.turn
+    }
+
+    /// Starts over with a new board, e.g. after a reset.
+    /// The turn still advances, so clients can't mistake the new board for a delta of an old turn.
+    pub(crate) fn reset(&mut self, board: &Board) {
+        self.previous.clone_from(&board.tiles);
+        self.changes.clear();
+        self.turn += 1;
+    }
+
+    /// Writes the `TURN`, `PREVIOUS` and `CHANGES` lines of a snapshot, the changes of the oldest turn first.
+    pub(crate) fn write_snapshot(&self, out: &mut Vec<String>) {
+        out.push(format!("TURN {}", self.turn));
+        out.push(format!("PREVIOUS {}", self.previous.iter().copied().map(Tile::to_char).collect::<String>()));
+        for changed in &self.changes {
+            let indices: Vec<_> = changed.iter().map(usize::to_string).collect();
+            out.push(format!("CHANGES {}", indices.join(" ")).trim_end().to_owned());
+        }
+    }
+
+    /// Restores the history saved by [`History::write_snapshot`] for `board`.
+    pub(crate) fn from_snapshot(fields: &Fields, board: &Board) -> Result<Self, String> {
+        let changes = fields.all("CHANGES").iter().map(|changed| values("CHANGES", changed));
+        let changes: VecDeque<Vec<usize>> = changes.collect::<Result<_, _>>()?;
+        if changes.iter().flatten().any(|&index| index >= board.tiles.len()) {
+            return Err("CHANGES contains a field outside of the board".to_owned());
+        }
+        Ok(History {
+            turn: value("TURN", fields.one("TURN")?)?,
+            previous: parse_tiles("PREVIOUS", fields.one("PREVIOUS")?, board.tiles.len())?,
+            changes,
+        })
+    }
+
+    /// Finishes the current turn, remembering all tiles that changed since the last call.
+    pub(crate) fn record(&mut self, board: &Board) {
+        let changed = board
+            .tiles
+            .iter()
+            .zip(&self.previous)
+            .enumerate()
+            .filter(|(_, (new, old))| new != old)
+            .map(|(index, _)| index)
+            .collect();
+        self.previous.clone_from(&board.tiles);
+        self.changes.push_back(changed);
+        if self.changes.len() > KEYFRAME_INTERVAL as usize {
+            self.changes.pop_front();
+        }
+        self.turn += 1;
+    }
+
+    /// Returns the sorted indices of all tiles that changed after `base`,
+    /// or `None` if `base` is not covered by the history anymore.
+    fn changes_since(&self, base: u64) -> Option<Vec<usize>> {
+        let behind = usize::try_from(self.turn.checked_sub(base)?).ok()?;
+        if behind > self.changes.len() {
+            return None;
+        }
+        let mut indices: Vec<usize> = self.changes.iter().rev().take(behind).flatten().copied().collect();
+        indices.sort_unstable();
+        indices.dedup();
+        Some(indices)
+    }
+
+    /// Decides what a client should be sent this turn.
+    /// `None` means a full keyframe, otherwise the changes since the last acknowledged turn.
+    pub(crate) fn update_for(&self, state: &DeltaState) -> Option<(u64, Vec<usize>)> {
+        if !state.enabled || self.turn.is_multiple_of(KEYFRAME_INTERVAL) {
+            return None;
+        }
+        let base = state.acked_turn?;
+        self.changes_since(base).map(|changes| (base, changes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DELTAS: DeltaState = DeltaState { enabled: true, acked_turn: None };
+
+    /// Plays `turns` turns on a 4x4 board, placing a stone on the field of the turn number each time.
+    fn play(board: &mut Board, history: &mut History, turns: u64) {
+        for _ in 0..turns {
+            let index = history.turn() as usize % board.tiles.len();
+            board.tiles[index] = if board.tiles[index] == Tile::Empty { Tile::Player(0) } else { Tile::Empty };
+            history.record(board);
+        }
+    }
+
+    fn acked(turn: u64) -> DeltaState {
+        DeltaState { acked_turn: Some(turn), ..DELTAS }
+    }
+
+    #[test]
+    fn deltas_are_opt_in_and_need_an_ack() {
+        let mut board = Board::new(4, 4);
+        let mut history = History::new(&board);
+        play(&mut board, &mut history, 3);
+        assert_eq!(history.update_for(&DeltaState::default()), None);
+        assert_eq!(history.update_for(&DELTAS), None);
+        assert_eq!(history.update_for(&acked(2)), Some((2, vec![2])));
+    }
+
+    #[test]
+    fn late_acks_get_all_changes_since() {
+        let mut board = Board::new(4, 4);
+        let mut history = History::new(&board);
+        play(&mut board, &mut history, 5);
+        assert_eq!(history.update_for(&acked(1)), Some((1, vec![1, 2, 3, 4])));
+        assert_eq!(history.update_for(&acked(5)), Some((5, vec![])));
+        // An acknowledged turn that hasn't happened yet can't be the base of a delta
+        assert_eq!(history.update_for(&acked(6)), None);
+    }
+
+    #[test]
+    fn fields_changing_back_and_forth_are_listed_once() {
+        let mut board = Board::new(4, 4);
+        let mut history = History::new(&board);
+        play(&mut board, &mut history, 20);
+        // The first 4 fields changed twice, field 0 is even back to what it was at turn 0
+        assert_eq!(history.update_for(&acked(0)), Some((0, (0..16).collect())));
+    }
+
+    #[test]
+    fn every_keyframe_interval_everybody_gets_a_keyframe() {
+        let mut board = Board::new(4, 4);
+        let mut history = History::new(&board);
+        play(&mut board, &mut history, KEYFRAME_INTERVAL - 1);
+        assert!(history.update_for(&acked(KEYFRAME_INTERVAL - 2)).is_some());
+        play(&mut board, &mut history, 1);
+        assert_eq!(history.update_for(&acked(KEYFRAME_INTERVAL - 1)), None);
+        play(&mut board, &mut history, 1);
+        assert!(history.update_for(&acked(KEYFRAME_INTERVAL)).is_some());
+    }
+
+    #[test]
+    fn clients_falling_behind_the_history_get_a_keyframe() {
+        let mut board = Board::new(4, 4);
+        let mut history = History::new(&board);
+        play(&mut board, &mut history, KEYFRAME_INTERVAL + 3);
+        let oldest = history.turn() - KEYFRAME_INTERVAL;
+        assert!(history.update_for(&acked(oldest)).is_some());
+        assert_eq!(history.update_for(&acked(oldest - 1)), None);
+    }
+
+    #[test]
+    fn resets_start_over_without_reusing_turns() {
+        let mut board = Board::new(4, 4);
+        let mut history = History::new(&board);
+        play(&mut board, &mut history, 3);
+        history.reset(&Board::new(4, 4));
+        assert_eq!(history.turn(), 4);
+        assert_eq!(history.update_for(&acked(3)), None);
+        assert_eq!(history.update_for(&acked(4)), Some((4, vec![])));
+    }
+}
@@ -0,0 +1,181 @@
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+use super::fog::UNSEEN;
+use super::{Board, PlayerId, Position, Suicide, Tile};
+
+/// A question a bot asks about the board, answered right away by [`Board::answer`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Query {
+    /// The stones, owner and liberties of the group on a field.
+    Group(Position),
+    /// The liberties of the group on a field.
+    Liberties(Position),
+    /// The empty fields enclosed by the stones of a single player.
+    Territory,
+    /// The fields the asking player may place a stone on.
+    Legal,
+}
+
+impl Board {
+    /// Answers a query of player `id`, who only sees the `visible` fields with the fog of war.
+    /// Returns `None` for fields outside the board.
+    pub(crate) fn answer(
+        &mut self,
+        query: Query,
+        id: Option<PlayerId>,
+        visible: Option<&[bool]>,
+        suicide: Suicide,
+    ) -> Option<String> {
+        let seen = |index: usize| visible.is_none_or(|visible| visible[index]);
+        let answer = match query {
+            Query::Group((x, y)) | Query::Liberties((x, y)) => {
+                if x >= self.width || y >= self.height {
+                    return None;
+                }
+                let index = self.index(x, y);
+                let owner = if seen(index) { self.tiles[index].to_char() } else { UNSEEN };
+                let stones = match self.tiles[index] {
+                    Tile::Player(_) | Tile::Neutral if seen(index) => self.group(x, y),
+                    _ => Vec::new(),
+                };
+                let liberties = self.liberties(&stones);
+                let mut stones: Vec<_> =
+                    stones.into_iter().map(|(x, y)| self.index(x, y)).filter(|&index| seen(index)).collect();
+                stones.sort_unstable();
+                let liberties: Vec<_> = liberties.into_iter().filter(|&index| seen(index)).collect();
+                let mut answer = match query {
+                    Query::Group(_) => format!("GROUP {x} {y} {owner} {}", liberties.len()),
+                    _ => format!("LIBERTIES {x} {y} {}", liberties.len()),
+                };
+                let listed = if matches!(query, Query::Group(_)) { stones } else { liberties };
+                let width = usize::from(self.width);
+                for index in listed {
+                    let _ = write!(answer, " {} {}", index % width, index / width);
+                }
+                answer
+            }
+            Query::Territory => {
+                let territory = self.territory(&seen);
+                let fields = territory.iter().enumerate().map(|(index, owner)| match owner {
+                    _ if !seen(index) => UNSEEN,
+                    Some(id) => Tile::Player(*id).to_char(),
+                    None => '.',
+                });
+                format!("TERRITORY {}", fields.collect::<String>())
+            }
+            Query::Legal => {
+                let mut answer = String::from("LEGAL ");
+                for index in 0..self.tiles.len() {
+                    let (x, y) = ((index % usize::from(self.width)) as u16, (index / usize::from(self.width)) as u16);
+                    answer.push(match id {
+                        _ if !seen(index) => UNSEEN,
+                        Some(id) if self.check_move(x, y, id, suicide).is_ok() => '1',
+                        _ => '0',
+                    });
+                }
+                answer
+            }
+        };
+        Some(answer)
+    }
+
+    /// The indices of the empty fields next to the given stones, each counted once.
+    fn liberties(&self, stones: &[Position]) -> BTreeSet<usize> {
+        let neighbors = stones.iter().flat_map(|&(x, y)| self.adjacent_filter(x, y, Tile::Empty));
+        neighbors.map(|(x, y)| self.index(x, y)).collect()
+    }
+
+    /// The owner of every field that is territory: an empty field in an area of empty fields that only borders stones
+    /// of that player, apart from walls and the edge of the board. Neutral stones and hidden fields spoil an area.
+    fn territory(&self, seen: &impl Fn(usize) -> bool) -> Vec<Option<PlayerId>> {
+        let mut owners = vec![None; self.tiles.len()];
+        let mut done = vec![false; self.tiles.len()];
+        let width = usize::from(self.width);
+        for start in 0..self.tiles.len() {
+            if done[start] || self.tiles[start] != Tile::Empty || !seen(start) {
+                continue;
+            }
+            done[start] = true;
+            let mut area = vec![start];
+            let mut borders = BTreeSet::new();
+            let mut spoiled = false;
+            let mut next = 0;
+            while let Some(&index) = area.get(next) {
+                next += 1;
+                for (x, y, tile) in self.adjacent_tiles((index % width) as u16, (index / width) as u16) {
+                    let neighbor = self.index(x, y);
+                    match tile {
+                        _ if !seen(neighbor) => spoiled = true,
+                        Tile::Empty if !done[neighbor] => {
+                            done[neighbor] = true;
+                            area.push(neighbor);
+                        }
+                        Tile::Player(id) => {
+                            borders.insert(id);
+                        }
+                        Tile::Neutral => spoiled = true,
+                        _ => (),
+                    }
+                }
+            }
+            if let (false, Some(&owner), 1) = (spoiled, borders.first(), borders.len()) {
+                for index in area {
+                    owners[index] = Some(owner);
+                }
+            }
+        }
+        owners
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn board(rows: &[&str]) -> Board {
+        let mut board = Board::new(rows[0].len() as u16, rows.len() as u16);
+        for (index, c) in rows.concat().chars().enumerate() {
+            board.tiles[index] = Tile::from_char(c).expect("valid tile");
+        }
+        board.rebuild_groups();
+        board
+    }
+
+    fn ask(board: &mut Board, query: Query) -> String {
+        board.answer(query, Some(0), None, Suicide::Before).expect("field on the board")
+    }
+
+    #[test]
+    fn groups_list_their_stones_and_distinct_liberties() {
+        let mut board = board(&["AA.", "A.B", "..."]);
+        assert_eq!(ask(&mut board, Query::Group((1, 0))), "GROUP 1 0 A 3 0 0 1 0 0 1");
+        assert_eq!(ask(&mut board, Query::Liberties((0, 1))), "LIBERTIES 0 1 3 2 0 1 1 0 2");
+        assert_eq!(ask(&mut board, Query::Group((1, 1))), "GROUP 1 1 . 0");
+        assert_eq!(board.answer(Query::Group((3, 0)), Some(0), None, Suicide::Before), None);
+    }
+
+    #[test]
+    fn territory_borders_a_single_player() {
+        let territory = |rows: &[&str]| ask(&mut board(rows), Query::Territory);
+        assert_eq!(territory(&[".A..", "AA.B", "...."]), "TERRITORY A...........");
+        assert_eq!(territory(&[".A/.", "AA/.", "...."]), "TERRITORY A..A...AAAAA");
+        assert_eq!(territory(&["#.A", "AAA", "..."]), "TERRITORY ......AAA");
+    }
+
+    #[test]
+    fn legal_moves_leave_out_occupied_fields_and_suicide() {
+        let mut board = board(&[".B.", "B..", "..."]);
+        assert_eq!(ask(&mut board, Query::Legal), "LEGAL 001011111");
+    }
+
+    #[test]
+    fn hidden_fields_are_left_out() {
+        let mut board = board(&["AA.", "..."]);
+        let visible = [true, false, true, true, true, true];
+        let answer = board.answer(Query::Group((0, 0)), Some(0), Some(&visible), Suicide::Before);
+        assert_eq!(answer.as_deref(), Some("GROUP 0 0 A 3 0 0"));
+        let answer = board.answer(Query::Legal, Some(0), Some(&visible), Suicide::Before);
+        assert_eq!(answer.as_deref(), Some("LEGAL 0?1111"));
+    }
+}
@@ -0,0 +1,68 @@
+use std::collections::BTreeSet;
+use std::time::{Duration, SystemTime};
+
+use super::spawn::Protection;
+use super::uf::UnionFind;
+use super::{Board, Tile};
+use crate::snapshot::{value, values, Fields};
+
+/// Parses the tiles of a board with `len` fields, written with [`Board::serialize`].
+pub(super) fn parse_tiles(key: &str, tiles: &str, len: usize) -> Result<Vec<Tile>, String> {
+    let tiles: Vec<_> = tiles.chars().map(Tile::from_char).collect::<Option<_>>().ok_or(format!("invalid {key}"))?;
+    if tiles.len() != len {
+        return Err(format!("{key} has {} tiles instead of {len}", tiles.len()));
+    }
+    Ok(tiles)
+}
+
+impl Board {
+    /// Writes the `BOARD`, `AGES` and `PROTECTED` lines of a snapshot.
+    /// The groups aren't saved, they are rebuilt from the tiles when the snapshot is restored.
+    pub(crate) fn write_snapshot(&self, out: &mut Vec<String>) {
+        let started = self.start.duration_since(SystemTime::UNIX_EPOCH).unwrap_or(Duration::ZERO).as_millis();
+        out.push(format!("BOARD {} {} {started} {}", self.width, self.height, self.serialize()));
+        out.push(format!("AGES {}", self.serialize_ages()));
+        for (index, protection) in self.protection.iter().enumerate() {
+            if let Some(Protection { id, turns }) = protection {
+                out.push(format!("PROTECTED {index} {id} {turns}"));
+            }
+        }
+    }
+
+    /// Restores a board saved by [`Board::write_snapshot`].
+    pub(crate) fn from_snapshot(fields: &Fields) -> Result<Self, String> {
+        let board: Vec<_> = fields.one("BOARD")?.split(' ').collect();
+        let [width, height, started, tiles] = board[..] else {
+            return Err("BOARD needs a width, a height, the start time and the tiles".to_owned());
+        };
+        let (width, height): (u16, u16) = (value("BOARD", width)?, value("BOARD", height)?);
+        let len = usize::from(width) * usize::from(height);
+        let ages = values("AGES", fields.one("AGES")?)?;
+        if ages.len() != len {
+            return Err(format!("AGES has {} fields instead of {len}", ages.len()));
+        }
+        let mut protection = vec![None; len];
+        for line in fields.all("PROTECTED") {
+            let [index, id, turns] = values::<usize>("PROTECTED", line)?[..] else {
+                return Err("PROTECTED needs a field, a player and the turns".to_owned());
+            };
+            let (id, turns) = (id.try_into(), u32::try_from(turns));
+            let (Some(field), Ok(id), Ok(turns @ 1..)) = (protection.get_mut(index), id, turns) else {
+                return Err(format!("invalid PROTECTED {line}"));
+            };
+            *field = Some(Protection { id, turns });
+        }
+        let mut board = Board {
+            tiles: parse_tiles("BOARD", tiles, len)?,
+            width,
+            height,
+            start: SystemTime::UNIX_EPOCH + Duration::from_millis(value("BOARD", started)?),
+            uf: UnionFind::default(),
+            protection,
+            protected: BTreeSet::new(),
+            ages,
+        };
+        board.rebuild_groups();
+        Ok(board)
+    }
+}
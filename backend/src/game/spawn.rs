@@ -0,0 +1,176 @@
+use super::{Board, PlayerId, Position, Tile};
+
+/// Keeps a field safe for a player for a few more turns.
+/// An empty field stays free for the player, a group with a protected stone of the player can't be captured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Protection {
+    pub(crate) id: PlayerId,
+    pub(crate) turns: u32,
+}
+
+impl Board {
+    /// Finds the top left corner of a `size` by `size` square of empty, unprotected fields, the topmost one first.
+    pub(crate) fn spawn_zone(&self, size: u16) -> Option<Position> {
+        let (width, height, size) = (usize::from(self.width), usize::from(self.height), usize::from(size));
+        if size == 0 || size > width || size > height {
+            return None;
+        }
+        // blocked[at(x, y)] counts the fields above and left of (x, y) that can't be part of a spawn zone
+        let at = |x: usize, y: usize| x + (width + 1) * y;
+        let mut blocked = vec![0; (width + 1) * (height + 1)];
+        for y in 0..height {
+            for x in 0..width {
+                let index = x + width * y;
+                let taken = self.tiles[index] != Tile::Empty || self.protection[index].is_some();
+                blocked[at(x + 1, y + 1)] =
+                    usize::from(taken) + blocked[at(x, y + 1)] + blocked[at(x + 1, y)] - blocked[at(x, y)];
+            }
+        }
+        let taken = |x: usize, y: usize| {
+            blocked[at(x + size, y + size)] + blocked[at(x, y)] - blocked[at(x + size, y)] - blocked[at(x, y + size)]
+        };
+        let corners = (0..=height - size).flat_map(|y| (0..=width - size).map(move |x| (x, y)));
+        corners.into_iter().find(|&(x, y)| taken(x, y) == 0).map(|(x, y)| (x as u16, y as u16))
+    }
+
+    /// Protects all fields of the `size` by `size` square at `(x, y)` for player `id`.
+    pub(crate) fn protect_zone(&mut self, (x, y): Position, size: u16, id: PlayerId, turns: u32) {
+        for row in y..y.saturating_add(size).min(self.height) {
+            for column in x..x.saturating_add(size).min(self.width) {
+                self.protect(column, row, id, turns);
+            }
+        }
+    }
+
+    pub(crate) fn protect(&mut self, x: u16, y: u16, id: PlayerId, turns: u32) {
+        let index = self.index(x, y);
+        self.protection[index] = (turns > 0).then_some(Protection { id, turns });
+        if turns > 0 {
+            self.protected.insert(index);
+        } else {
+            self.protected.remove(&index);
+        }
+    }
+
+    /// Whether the field is kept free for a player other than `id`.
+    pub(super) fn is_protected_from(&self, index: usize, id: PlayerId) -> bool {
+        self.protection[index].is_some_and(|protection| protection.id != id)
+    }
+
+    /// Whether the group at `index` contains a protected stone.
+    pub(super) fn is_protected_group(&mut self, index: usize) -> bool {
+        if self.protected.is_empty() {
+            return false;
+        }
+        let root = self.uf.find(index);
+        self.protected.iter().any(|&other| {
+            let Tile::Player(id) = self.tiles[other] else { return false };
+            self.protection[other].is_some_and(|protection| protection.id == id) && self.uf.find(other) == root
+        })
+    }
+
+    /// Counts down the protection of all fields by a turn.
+    /// Groups without liberties that lost their last protected stone are captured now, returning the removed stones.
+    pub(crate) fn expire_protection(&mut self) -> usize {
+        let mut expired = Vec::new();
+        for &index in &self.protected {
+            let protection = &mut self.protection[index];
+            let Some(Protection { turns, .. }) = protection else { continue };
+            *turns -= 1;
+            if *turns == 0 {
+                *protection = None;
+                expired.push(index);
+            }
+        }
+        for index in &expired {
+            self.protected.remove(index);
+        }
+        let width = usize::from(self.width);
+        let mut removed = 0;
+        for index in expired {
+            let captured = matches!(self.tiles[index], Tile::Player(_)) && self.uf.get_liberties(index) == 0;
+            if captured && !self.is_protected_group(index) {
+                removed += self.remove_group((index % width) as u16, (index / width) as u16);
+            }
+        }
+        removed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: PlayerId = 0;
+    const B: PlayerId = 1;
+
+    fn board(rows: &[&str]) -> Board {
+        let mut board = Board::new(rows[0].len() as u16, rows.len() as u16);
+        for (index, c) in rows.concat().chars().enumerate() {
+            board.tiles[index] = Tile::from_char(c).expect("valid tile");
+        }
+        board.rebuild_groups();
+        board
+    }
+
+    #[test]
+    fn spawn_zones_are_the_topmost_free_squares() {
+        let mut board = board(&["A...", "....", "..#.", "...."]);
+        assert_eq!(board.spawn_zone(1), Some((1, 0)));
+        assert_eq!(board.spawn_zone(2), Some((1, 0)));
+        assert_eq!(board.spawn_zone(3), None);
+        assert_eq!(board.spawn_zone(0), None);
+        assert_eq!(board.spawn_zone(5), None);
+        board.protect(1, 1, B, 3);
+        assert_eq!(board.spawn_zone(2), Some((2, 0)));
+        board.protect(3, 1, B, 3);
+        assert_eq!(board.spawn_zone(2), Some((0, 2)));
+    }
+
+    #[test]
+    fn zones_are_protected_from_other_players_only() {
+        let mut board = board(&["....", "....", "...."]);
+        board.protect_zone((2, 1), 3, A, 3);
+        let protected: Vec<_> = (0..board.tiles.len()).filter(|&index| board.is_protected_from(index, B)).collect();
+        assert_eq!(protected, [6, 7, 10, 11]);
+        assert!((0..board.tiles.len()).all(|index| !board.is_protected_from(index, A)));
+        board.protect_zone((2, 1), 3, A, 0);
+        assert!((0..board.tiles.len()).all(|index| !board.is_protected_from(index, B)));
+    }
+
+    #[test]
+    fn protection_expires_and_captures_groups_without_liberties() {
+        let mut board = board(&["BAAB", "/BB/"]);
+        board.protect(1, 0, A, 1);
+        board.protect(2, 0, A, 2);
+        assert!(board.is_protected_group(1));
+        assert_eq!(board.expire_protection(), 0);
+        assert_eq!(board.protection[1], None);
+        assert_eq!(board.protection[2], Some(Protection { id: A, turns: 1 }));
+        assert!(board.is_protected_group(1));
+        assert_eq!(board.expire_protection(), 2);
+        assert_eq!(board.tiles[1..3], [Tile::Empty, Tile::Empty]);
+        assert!(board.protection.iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn protection_of_other_players_does_not_protect_a_group() {
+        let mut board = board(&["BAB", "/B/"]);
+        board.protect(1, 0, B, 2);
+        assert!(!board.is_protected_group(1));
+        board.protect(1, 1, B, 2);
+        assert!(board.is_protected_group(4));
+    }
+
+    #[test]
+    fn protection_moves_with_the_fields_when_the_board_grows() {
+        let mut board = board(&["/B/", "BAB", "/B/"]);
+        board.protect(1, 1, A, 2);
+        assert!(board.grow(1, 10));
+        let index = board.index(1, 1);
+        assert!(board.is_protected_group(index));
+        assert_eq!(board.expire_protection(), 0);
+        assert_eq!(board.expire_protection(), 1);
+        assert_eq!(board.tile(1, 1), Some(Tile::Empty));
+    }
+}
@@ -0,0 +1,446 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::str::FromStr;
+
+use super::capture::Capture;
+use super::{Board, PlayerId, Tile};
+
+/// When a move without liberties that captures nothing is rejected as suicide.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum Suicide {
+    /// Judged by [`Board::try_place`] on the board before this turn.
+    #[default]
+    Before,
+    /// Judged by [`Board::take_back_suicides`] once all moves of the turn are placed.
+    After,
+    /// Never rejected, the group is removed as a self-capture instead.
+    Allow,
+}
+
+impl FromStr for Suicide {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "before" => Ok(Suicide::Before),
+            "after" => Ok(Suicide::After),
+            "allow" => Ok(Suicide::Allow),
+            _ => Err(()),
+        }
+    }
+}
+
+/// What the captures of a turn did, see [`Board::capture`].
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct Captures {
+    /// Captured stones, including converted ones.
+    pub(crate) stones: usize,
+    /// The captured stones of every player that placed a stone next to a captured group this turn.
+    pub(crate) credit: BTreeMap<PlayerId, usize>,
+    /// Stones removed because their own group had no liberties left after the captures.
+    pub(crate) self_captured: usize,
+}
+
+impl Board {
+    /// Turns the moves of this turn, made with [`Board::try_place`], into stones, all at once.
+    /// Fields several players tried to place a stone on stay empty. Returns the stones that made it onto the board.
+    pub(crate) fn place_stones(&mut self, moves: &[(PlayerId, u16, u16)]) -> Vec<(PlayerId, u16, u16)> {
+        let mut placed = Vec::new();
+        for &(id, x, y) in moves {
+            let index = self.index(x, y);
+            match self.tiles[index] {
+                Tile::TryPlace(other) if other == id => {
+                    self.tiles[index] = Tile::Player(id);
+                    placed.push((id, x, y));
+                }
+                Tile::Contested => self.tiles[index] = Tile::Empty,
+                _ => (),
+            }
+        }
+        let new: BTreeSet<_> = placed.iter().map(|&(_, x, y)| self.index(x, y)).collect();
+        // A new stone takes the field away as a liberty from every stone next to it that was there before
+        for &(_, x, y) in &placed {
+            let index = self.index(x, y);
+            let liberties = self.adjacent_filter(x, y, Tile::Empty).count();
+            self.uf.reset_node(index, liberties);
+            self.ages[index] = 0;
+            for (x, y, tile) in self.adjacent_tiles(x, y).collect::<Vec<_>>() {
+                let neighbor = self.index(x, y);
+                if matches!(tile, Tile::Player(_) | Tile::Neutral) && !new.contains(&neighbor) {
+                    let root = self.uf.find(neighbor);
+                    self.uf.add_liberty(root, -1);
+                }
+            }
+        }
+        for &(id, x, y) in &placed {
+            let index = self.index(x, y);
+            for (x, y) in self.adjacent_filter(x, y, Tile::Player(id)).collect::<Vec<_>>() {
+                self.uf.union(index, self.index(x, y));
+            }
+        }
+        placed
+    }
+
+    /// Takes back the stones placed this turn in groups without liberties that capture nothing, see [`Suicide::After`].
+    /// All groups are judged on the same board, before any of the stones are taken back.
+    /// Removes the taken back moves from `placed` and returns how many there were.
+    pub(crate) fn take_back_suicides(&mut self, placed: &mut Vec<(PlayerId, u16, u16)>) -> usize {
+        // Whether the group of each placed stone without liberties captures something, by the root of the group
+        let mut captures = BTreeMap::<usize, bool>::new();
+        for &(id, x, y) in placed.iter() {
+            let index = self.index(x, y);
+            if self.uf.get_liberties(index) > 0 {
+                continue;
+            }
+            let root = self.uf.find(index);
+            let mut captured = false;
+            for (x, y, tile) in self.adjacent_tiles(x, y).collect::<Vec<_>>() {
+                let neighbor = self.index(x, y);
+                let opponent = matches!(tile, Tile::Player(other) if other != id) || tile == Tile::Neutral;
+                captured |= opponent && self.uf.get_liberties(neighbor) == 0 && !self.is_protected_group(neighbor);
+            }
+            *captures.entry(root).or_default() |= captured;
+        }
+        let mut suicides = BTreeSet::new();
+        for &(_, x, y) in placed.iter() {
+            let index = self.index(x, y);
+            let root = self.uf.find(index);
+            if captures.get(&root) == Some(&false) {
+                suicides.insert(index);
+            }
+        }
+        if suicides.is_empty() {
+            return 0;
+        }
+        for &index in &suicides {
+            self.tiles[index] = Tile::Empty;
+        }
+        self.rebuild_groups();
+        placed.retain(|&(_, x, y)| !suicides.contains(&self.index(x, y)));
+        suicides.len()
+    }
+
+    /// Carries out the captures after [`Board::place_stones`], independent of the order of the moves:
+    /// 1. Every group without liberties next to a stone another player placed this turn is captured, as decided by
+    ///    `capture` and `convert_neutral`. All captures are decided on the same board and carried out together,
+    ///    so two groups can capture each other.
+    /// 2. Groups that still have no liberties and contain a stone placed this turn are removed as self-captures,
+    ///    unless one of their stones placed this turn captured something. Converted stones don't free any liberties,
+    ///    so the capturing group may be left without liberties.
+    ///
+    /// Groups with a protected stone are neither captured nor removed. Converted stones stay on the board even
+    /// without liberties, until a stone placed next to them captures them.
+    pub(crate) fn capture(
+        &mut self,
+        placed: &[(PlayerId, u16, u16)],
+        capture: Capture,
+        convert_neutral: bool,
+    ) -> Captures {
+        let mut captures = Captures::default();
+        // The captured groups by their root, with a stone of each and the players that captured it
+        let mut captured = BTreeMap::<usize, ((u16, u16), BTreeSet<PlayerId>)>::new();
+        // The placed stones next to a captured group
+        let mut capturing = BTreeSet::new();
+        for &(id, stone_x, stone_y) in placed {
+            for (x, y, tile) in self.adjacent_tiles(stone_x, stone_y).collect::<Vec<_>>() {
+                let index = self.index(x, y);
+                let opponent = match tile {
+                    Tile::Player(other) => other != id,
+                    Tile::Neutral => true,
+                    _ => false,
+                };
+                if opponent && self.uf.get_liberties(index) == 0 && !self.is_protected_group(index) {
+                    let root = self.uf.find(index);
+                    captured.entry(root).or_insert(((x, y), BTreeSet::new())).1.insert(id);
+                    capturing.insert((id, self.index(stone_x, stone_y)));
+                }
+            }
+        }
+        let mut changes = Vec::new();
+        for ((x, y), capturers) in captured.into_values() {
+            let group = self.group(x, y);
+            let targets = self.capture_targets(&group, capture, convert_neutral);
+            tracing::debug!(x, y, stones = group.len(), ?capturers, ?capture, "capturing group");
+            captures.stones += group.len();
+            for id in capturers {
+                *captures.credit.entry(id).or_default() += group.len();
+            }
+            changes.extend(group.into_iter().zip(targets));
+        }
+        for &((x, y), tile) in &changes {
+            let index = self.index(x, y);
+            self.tiles[index] = tile;
+            self.ages[index] = 0;
+        }
+        if !changes.is_empty() {
+            self.rebuild_groups();
+        }
+
+        // Capturing stones that were captured themselves don't keep anything alive
+        let capturing: BTreeSet<_> = capturing
+            .into_iter()
+            .filter(|&(id, index)| self.tiles[index] == Tile::Player(id))
+            .map(|(_, index)| self.uf.find(index))
+            .collect();
+        let mut dead = BTreeSet::new();
+        for &(id, x, y) in placed {
+            let index = self.index(x, y);
+            if self.tiles[index] == Tile::Player(id)
+                && self.uf.get_liberties(index) == 0
+                && !capturing.contains(&self.uf.find(index))
+                && !self.is_protected_group(index)
+            {
+                dead.extend(self.group(x, y));
+            }
+        }
+        for &(x, y) in &dead {
+            tracing::debug!(x, y, "removing self-captured stone");
+            let index = self.index(x, y);
+            self.tiles[index] = Tile::Empty;
+            self.ages[index] = 0;
+        }
+        if !dead.is_empty() {
+            self.rebuild_groups();
+        }
+        captures.self_captured = dead.len();
+        captures
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::Rejection;
+
+    const A: PlayerId = 0;
+    const B: PlayerId = 1;
+    const C: PlayerId = 2;
+
+    fn board(rows: &[&str]) -> Board {
+        let mut board = Board::new(rows[0].len() as u16, rows.len() as u16);
+        for (index, c) in rows.concat().chars().enumerate() {
+            board.tiles[index] = Tile::from_char(c).expect("valid tile");
+        }
+        board.rebuild_groups();
+        board
+    }
+
+    fn rows(board: &Board) -> Vec<String> {
+        board.serialize().chars().collect::<Vec<_>>().chunks(usize::from(board.width)).map(String::from_iter).collect()
+    }
+
+    fn permutations<T: Clone>(items: &[T]) -> Vec<Vec<T>> {
+        if items.len() <= 1 {
+            return vec![items.to_vec()];
+        }
+        let mut all = Vec::new();
+        for i in 0..items.len() {
+            let mut rest = items.to_vec();
+            let first = rest.remove(i);
+            for mut permutation in permutations(&rest) {
+                permutation.insert(0, first.clone());
+                all.push(permutation);
+            }
+        }
+        all
+    }
+
+    /// Plays a turn with the moves in every possible order and checks that the outcome is always the same,
+    /// and that the liberties kept up to date match the ones computed from scratch.
+    fn play(rows_before: &[&str], moves: &[(PlayerId, u16, u16)], capture: Capture) -> (Vec<String>, Captures) {
+        play_with(rows_before, moves, capture, Suicide::Before, false)
+    }
+
+    fn play_with(
+        rows_before: &[&str],
+        moves: &[(PlayerId, u16, u16)],
+        capture: Capture,
+        suicide: Suicide,
+        convert_neutral: bool,
+    ) -> (Vec<String>, Captures) {
+        let mut outcomes = permutations(moves).into_iter().map(|moves| {
+            let mut board = board(rows_before);
+            let moves: Vec<_> =
+                moves.into_iter().filter(|&(id, x, y)| board.try_place(x, y, id, suicide).is_ok()).collect();
+            let mut placed = board.place_stones(&moves);
+            if suicide == Suicide::After {
+                board.take_back_suicides(&mut placed);
+            }
+            let captures = board.capture(&placed, capture, convert_neutral);
+            let mut rebuilt = board.clone();
+            rebuilt.rebuild_groups();
+            let stone = |index: &usize| matches!(board.tiles[*index], Tile::Player(_) | Tile::Neutral);
+            for index in (0..board.tiles.len()).filter(stone) {
+                assert_eq!(board.uf.get_liberties(index), rebuilt.uf.get_liberties(index), "liberties of {index}");
+            }
+            (rows(&board), captures)
+        });
+        let first = outcomes.next().expect("at least one order");
+        for outcome in outcomes {
+            assert_eq!(outcome, first);
+        }
+        first
+    }
+
+    #[test]
+    fn surrounded_group_is_captured() {
+        let (after, captures) = play(&[".A.", "AB.", ".A."], &[(A, 2, 1)], Capture::Remove);
+        assert_eq!(after, [".A.", "A.A", ".A."]);
+        assert_eq!(captures.stones, 1);
+        assert_eq!(captures.credit, BTreeMap::from([(A, 1)]));
+    }
+
+    #[test]
+    fn groups_can_capture_each_other() {
+        let (after, captures) = play(&["B..A."], &[(A, 1, 0), (B, 2, 0)], Capture::Remove);
+        assert_eq!(after, ["...A."]);
+        assert_eq!(captures.stones, 3);
+        assert_eq!(captures.credit, BTreeMap::from([(A, 2), (B, 1)]));
+        assert_eq!(captures.self_captured, 0);
+    }
+
+    #[test]
+    fn capturers_share_the_credit() {
+        let (after, captures) = play(&[".A.", ".B.", ".A."], &[(A, 2, 1), (C, 0, 1)], Capture::Remove);
+        assert_eq!(after, [".A.", "C.A", ".A."]);
+        assert_eq!(captures.credit, BTreeMap::from([(A, 1), (C, 1)]));
+    }
+
+    #[test]
+    fn contested_fields_capture_nothing() {
+        let (after, captures) = play(&[".A.", "AB.", ".A."], &[(A, 2, 1), (C, 2, 1)], Capture::Remove);
+        assert_eq!(after, [".A.", "AB.", ".A."]);
+        assert_eq!(captures, Captures::default());
+    }
+
+    #[test]
+    fn filling_the_own_last_liberties_is_a_self_capture() {
+        let before = [".BBB.", "BA..B", ".BBB."];
+        let (after, captures) = play(&before, &[(A, 2, 1), (A, 3, 1)], Capture::Remove);
+        assert_eq!(after, [".BBB.", "B...B", ".BBB."]);
+        assert_eq!(captures.stones, 0);
+        assert_eq!(captures.self_captured, 3);
+    }
+
+    #[test]
+    fn captured_groups_go_to_the_majority() {
+        let moves = [(A, 2, 1), (C, 0, 1)];
+        let (after, _) = play(&[".A.", ".B.", ".A."], &moves, Capture::Majority);
+        assert_eq!(after, [".A.", "CAA", ".A."]);
+        let (after, _) = play(&[".A.", ".B.", ".C."], &[(A, 2, 1), (C, 0, 1)], Capture::Majority);
+        assert_eq!(after, [".A.", "C#A", ".C."]);
+    }
+
+    #[test]
+    fn mutual_captures_are_converted_on_the_same_board() {
+        let (after, captures) = play(&["B..A."], &[(A, 1, 0), (B, 2, 0)], Capture::Majority);
+        assert_eq!(after, ["ABAA."]);
+        assert_eq!(captures.stones, 3);
+    }
+
+    #[test]
+    fn split_hands_out_stones_to_their_neighbors() {
+        let before = ["AAAA.", "BBBB.", "CCCC.", "....."];
+        let (after, captures) = play(&before, &[(A, 4, 1), (C, 4, 2)], Capture::Split);
+        assert_eq!(after, ["AAAA.", "###AA", "CCCCC", "....."]);
+        assert_eq!(captures.stones, 4);
+        assert_eq!(captures.credit, BTreeMap::from([(A, 4)]));
+    }
+
+    #[test]
+    fn liberties_shared_by_several_stones_are_counted_consistently() {
+        // The empty field in the middle touches the group of A three times
+        let before = ["AAA..", "A.A..", ".....", "....."];
+        let (after, captures) = play(&before, &[(B, 1, 1), (B, 0, 2), (A, 3, 3)], Capture::Remove);
+        assert_eq!(after, ["AAA..", "ABA..", "B....", "...A."]);
+        assert_eq!(captures, Captures::default());
+    }
+
+    #[test]
+    fn filling_the_last_own_liberty_is_suicide() {
+        let (after, _) = play(&["A.A", "AAA"], &[(A, 1, 0)], Capture::Remove);
+        assert_eq!(after, ["A.A", "AAA"]);
+        let (after, captures) = play_with(&["A.A", "AAA"], &[(A, 1, 0)], Capture::Remove, Suicide::Allow, false);
+        assert_eq!(after, ["...", "..."]);
+        assert_eq!(captures.self_captured, 6);
+    }
+
+    #[test]
+    fn moves_next_to_own_groups_with_other_liberties_are_no_suicide() {
+        // The field is a liberty of both stones of the group next to it, which has one more
+        let (after, _) = play(&["A.A", "AA."], &[(A, 1, 0)], Capture::Remove);
+        assert_eq!(after, ["AAA", "AA."]);
+    }
+
+    #[test]
+    fn moves_into_an_opponents_eye_are_suicide() {
+        let (after, _) = play(&[".A.", "A.A", ".A."], &[(B, 1, 1)], Capture::Remove);
+        assert_eq!(after, [".A.", "A.A", ".A."]);
+    }
+
+    #[test]
+    fn capturing_moves_are_no_suicide() {
+        let (after, captures) = play(&["A.A"], &[(B, 1, 0)], Capture::Remove);
+        assert_eq!(after, [".B."]);
+        assert_eq!(captures.stones, 2);
+        let (after, _) = play_with(&["A.A"], &[(B, 1, 0)], Capture::Remove, Suicide::After, false);
+        assert_eq!(after, [".B."]);
+    }
+
+    #[test]
+    fn protected_groups_are_not_captured_by_a_move_into_their_last_liberty() {
+        let mut board = board(&["A.A"]);
+        board.protect(0, 0, A, 5);
+        board.protect(2, 0, A, 5);
+        assert_eq!(board.try_place(1, 0, B, Suicide::Before), Err(Rejection::Suicide));
+        assert_eq!(board.try_place(1, 0, B, Suicide::After), Ok(()));
+    }
+
+    #[test]
+    fn suicide_after_the_other_moves_takes_the_stones_back() {
+        let before = [".BBB.", "BA..B", ".BBB."];
+        let moves = [(A, 2, 1), (A, 3, 1)];
+        let (after, captures) = play_with(&before, &moves, Capture::Remove, Suicide::After, false);
+        assert_eq!(after, before);
+        assert_eq!(captures, Captures::default());
+        let (after, captures) = play_with(&before, &moves, Capture::Remove, Suicide::Allow, false);
+        assert_eq!(after, [".BBB.", "B...B", ".BBB."]);
+        assert_eq!(captures.self_captured, 3);
+    }
+
+    #[test]
+    fn captured_neutral_groups_are_removed_or_converted() {
+        let before = ["A#A.", "A#.A", ".A.."];
+        let (after, captures) = play_with(&before, &[(A, 2, 1)], Capture::Remove, Suicide::Before, false);
+        assert_eq!(after, ["A.A.", "A.AA", ".A.."]);
+        assert_eq!(captures.credit, BTreeMap::from([(A, 2)]));
+        let (after, captures) = play_with(&before, &[(A, 2, 1)], Capture::Remove, Suicide::Before, true);
+        assert_eq!(after, ["AAA.", "AAAA", ".A.."]);
+        assert_eq!(captures.stones, 2);
+    }
+
+    #[test]
+    fn neutral_groups_with_a_tie_next_to_them_are_removed_when_converting() {
+        let moves = [(A, 0, 1), (B, 2, 1), (C, 1, 2)];
+        let (after, _) = play_with(&["A#B", ".#.", "..."], &moves, Capture::Remove, Suicide::Before, true);
+        assert_eq!(after, ["A.B", "A.B", ".C."]);
+    }
+
+    #[test]
+    fn converting_captures_keep_the_capturing_group() {
+        for capture in [Capture::Majority, Capture::Split] {
+            let (after, captures) = play(&["AB."], &[(A, 2, 0)], capture);
+            assert_eq!(after, ["AAA"]);
+            assert_eq!(captures, Captures { stones: 1, credit: BTreeMap::from([(A, 1)]), self_captured: 0 });
+            let (after, captures) = play(&["/A/", "AB.", "/A/"], &[(A, 2, 1)], capture);
+            assert_eq!(after, ["/A/", "AAA", "/A/"]);
+            assert_eq!(captures, Captures { stones: 1, credit: BTreeMap::from([(A, 1)]), self_captured: 0 });
+        }
+    }
+
+    #[test]
+    fn neutral_captures_keep_the_capturing_group() {
+        let (after, captures) = play(&["AB."], &[(A, 2, 0)], Capture::Neutral);
+        assert_eq!(after, ["A#A"]);
+        assert_eq!(captures.self_captured, 0);
+    }
+}
@@ -11,10 +11,10 @@ impl UnionFind {
         // Set initial liberties substracting the rim
         let liberties = (0..n).map(|i| {
             let x = i % width;
-            let y = i / height;
-            return 4 // Default
-                - (x % (width - 1) == 0 && y % (height - 1) == 0) as usize  // corners
-                - (x % (width - 1) == 0 || y % (height - 1) == 0) as usize; // borders
+            let y = i / width;
+            4 // Default
+                - (x.is_multiple_of(width - 1) && y.is_multiple_of(height - 1)) as usize // corners
+                - (x.is_multiple_of(width - 1) || y.is_multiple_of(height - 1)) as usize // borders
         });
         UnionFind {
             parent: (0..n).collect(),
@@ -23,6 +23,15 @@ impl UnionFind {
         }
     }
 
+    /// Every node in its own group, with the given liberties.
+    pub fn from_liberties(liberties: Vec<usize>) -> Self {
+        UnionFind {
+            parent: (0..liberties.len()).collect(),
+            size: vec![1; liberties.len()],
+            liberties,
+        }
+    }
+
     pub fn find(&mut self, x: usize) -> usize {
         if self.parent[x] != x {
             self.parent[x] = self.find(self.parent[x]);
@@ -56,9 +65,15 @@ impl UnionFind {
         self.liberties[root]
     }
 
-    pub fn reset_node(&mut self, x: usize) {
-        self.parent[x] = x; // Reset to point to itself
+    pub fn get_size(&mut self, x: usize) -> usize {
+        let root = self.find(x);
+        self.size[root]
+    }
+
+    /// Takes a node out of its group, e.g. when a stone is placed on or removed from its field.
+    pub fn reset_node(&mut self, x: usize, liberties: usize) {
+        self.parent[x] = x;
         self.size[x] = 1;
-        self.liberties[x] = 4; // Reset liberties to initial value
+        self.liberties[x] = liberties;
     }
 }
@@ -1,163 +1,797 @@
+mod admin;
+mod config;
 mod game;
+mod metrics;
 mod network;
+mod snapshot;
 
-use std::io::{ErrorKind, Write};
-use std::net::{SocketAddr, TcpStream};
-use std::time::Duration;
-use std::{net::TcpListener, str::FromStr};
+use std::cell::OnceCell;
+use std::io::ErrorKind;
+use std::collections::{BTreeMap, BTreeSet, HashSet, VecDeque};
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
 
-use network::{Connection, FrontendMessage, UserAuth};
-use tungstenite::WebSocket;
+use mio::Token;
+use network::{Admin, Connection, Delta, DropPolicy, End, Frontend, FrontendMessage, Network, UserAuth};
+use tracing::{debug, error, info, trace, warn};
+use tracing_subscriber::EnvFilter;
+use tungstenite::Message;
 
-use crate::game::Board;
+use crate::config::{Config, LogFormat};
+use crate::metrics::Metrics;
+use crate::game::{
+    player_char, Board, Decay, DeltaState, Encoding, History, PlayerId, Query, Rejection, Suicide, MAX_PLAYERS,
+};
 use crate::network::{Command, Error};
 
 #[derive(Debug)]
 struct GameState {
-    users: Vec<Connection>,
+    users: BTreeMap<Token, Connection>,
     user_auth: UserAuth,
     board: Board,
-    chars: Vec<Option<SocketAddr>>,
-    disconnected: Vec<SocketAddr>,
-    frontend: Option<WebSocket<TcpStream>>,
+    history: History,
+    players: Vec<Option<SocketAddr>>,
+    /// The stones every player has saved up in the action budget mode, by player.
+    budgets: Vec<usize>,
+    /// The free, protected stones every player has left, see `--handicap`.
+    handicaps: Vec<usize>,
+    /// Players that got a slot and still need a spawn zone, see `--spawn-zone`.
+    newcomers: BTreeSet<PlayerId>,
+    /// Player slots kept free for the users that played them before the server was resumed from a snapshot.
+    /// A user gets the slot back by logging in again.
+    reserved: BTreeMap<String, PlayerId>,
+    /// Connections waiting for a free player slot, in the order they will be admitted.
+    waiting: VecDeque<Token>,
+    /// Users to remove at the end of the tick, with the reason for the metrics.
+    disconnected: Vec<(Token, &'static str)>,
+    frontends: BTreeMap<Token, Frontend>,
+    admins: BTreeMap<Token, Admin>,
+    /// IP addresses that aren't allowed to connect anymore.
+    bans: HashSet<IpAddr>,
+    /// The board a reset goes back to.
+    map: Board,
+    tick: Duration,
+    /// No turns are played while paused, but input is still read.
+    paused: bool,
+    /// Whether a game is running. A stopped game keeps its final board until the next one is started.
+    running: bool,
+    metrics: Metrics,
+    config: Config,
 }
 
 impl GameState {
-    fn new(size: u16) -> Self {
+    fn new(size: u16, config: Config) -> Self {
+        let board = Board::new(size, size);
         GameState {
-            board: Board::new(size, size),
-            chars: vec![None; 'z' as usize - 'A' as usize],
-            users: Vec::new(),
+            history: History::new(&board),
+            map: board.clone(),
+            board,
+            players: vec![None; config.max_players.min(MAX_PLAYERS)],
+            budgets: vec![0; config.max_players.min(MAX_PLAYERS)],
+            handicaps: vec![0; config.max_players.min(MAX_PLAYERS)],
+            newcomers: BTreeSet::new(),
+            reserved: BTreeMap::new(),
+            waiting: VecDeque::new(),
+            users: BTreeMap::new(),
             user_auth: UserAuth::default(),
             disconnected: Vec::new(),
-            frontend: None,
+            frontends: BTreeMap::new(),
+            admins: BTreeMap::new(),
+            bans: HashSet::new(),
+            tick: config.tick,
+            paused: false,
+            running: true,
+            metrics: Metrics::default(),
+            config,
         }
     }
 
     fn process_user_input(&mut self) {
-        for user in self.users.iter_mut() {
+        let config = &self.config;
+        let mut total_commands = 0;
+        let mut logins = Vec::new();
+        for (&token, user) in self.users.iter_mut() {
+            let mut commands = 0;
+            let mut queries = 0;
             loop {
-                match network::parse_line(&mut user.stream, Command::from_str) {
-                    Ok(Command::Login(username, password)) => {
+                if commands == config.max_commands_per_tick {
+                    let ignored = user.incoming.discard();
+                    if ignored > 0 {
+                        warn!(parent: &user.span, ignored, "too many commands");
+                        if user.send_error(&Error::TooManyCommands(ignored)).is_err() {
+                            self.disconnected.push((token, "send_buffer_full"));
+                        }
+                    }
+                    break;
+                }
+                let command = match network::parse_line(&mut user.incoming, Command::from_str) {
+                    Ok(command) => command,
+                    Err(Error::WouldBlock) => break,
+                    Err(error) => {
+                        commands += 1;
+                        debug!(parent: &user.span, "invalid command: {error}");
+                        if user.send_error(&error).is_err() {
+                            self.disconnected.push((token, "send_buffer_full"));
+                            break;
+                        }
+                        user.invalid_commands += 1;
+                        if user.invalid_commands > config.max_invalid_commands {
+                            warn!(parent: &user.span, "kicking: too many invalid commands");
+                            // The connection is closed anyway, so it doesn't matter if this doesn't fit
+                            let _ = user.send_error(&Error::TooManyInvalidCommands);
+                            self.disconnected.push((token, "invalid_commands"));
+                            break;
+                        }
+                        continue;
+                    }
+                };
+                commands += 1;
+                trace!(parent: &user.span, ?command, "command");
+                match command {
+                    Command::Login(username, password) => {
                         if let Some(username) = self.user_auth.is_valid_or_insert(username, password) {
+                            user.span.record("username", username.as_str());
                             user.username = Some(username);
+                            logins.push(token);
                         } else {
-                            eprintln!("Invalid Credentials");
+                            info!(parent: &user.span, "invalid credentials");
+                            if user.send_error(&Error::InvalidCredentials).is_err() {
+                                self.disconnected.push((token, "send_buffer_full"));
+                                break;
+                            }
                         }
                     }
-                    Ok(Command::Put(pos)) => user.next_stone = Some(pos),
-                    Err(Error::WouldBlock) => break,
-                    Err(Error::ConnectionLost) => {
-                        self.disconnected.push(user.addr);
-                        eprintln!("Lost connection to {}", user.addr);
-                        break;
+                    Command::Put(_) | Command::Puts(_) if user.id.is_none() => {
+                        self.metrics.reject_move("not_playing");
+                        if user.send_error(&Error::NotPlaying).is_err() {
+                            self.disconnected.push((token, "send_buffer_full"));
+                            break;
+                        }
+                    }
+                    Command::Put(_) | Command::Puts(_) if !self.running => {
+                        self.metrics.reject_move("game_stopped");
+                        if user.send_error(&Error::GameStopped).is_err() {
+                            self.disconnected.push((token, "send_buffer_full"));
+                            break;
+                        }
+                    }
+                    Command::Put(pos) => user.next_stones = vec![pos],
+                    Command::Puts(mut positions) => {
+                        let mut seen = HashSet::new();
+                        positions.retain(|&pos| seen.insert(pos));
+                        user.next_stones = positions;
+                    }
+                    Command::Delta(enabled) => {
+                        user.delta = DeltaState {
+                            enabled,
+                            acked_turn: None,
+                        }
+                    }
+                    Command::Ack(turn) => user.delta.acked_turn = Some(turn),
+                    Command::Encoding(encoding) => user.encoding = encoding,
+                    Command::Ages(enabled) => user.ages = enabled,
+                    Command::Query(_) if queries == config.max_queries_per_tick => {
+                        if user.send_error(&Error::TooManyQueries).is_err() {
+                            self.disconnected.push((token, "send_buffer_full"));
+                            break;
+                        }
+                    }
+                    Command::Query(Query::Legal) if user.id.is_none() => {
+                        if user.send_error(&Error::NotPlaying).is_err() {
+                            self.disconnected.push((token, "send_buffer_full"));
+                            break;
+                        }
+                    }
+                    Command::Query(query) => {
+                        queries += 1;
+                        let visible = config.fog.map(|radius| self.board.visible(user.id, radius));
+                        let sent = match self.board.answer(query, user.id, visible.as_deref(), config.suicide) {
+                            Some(answer) => user.send(format!("{answer}\n").as_bytes()),
+                            None => user.send_error(&Error::InvalidArgument),
+                        };
+                        if sent.is_err() {
+                            self.disconnected.push((token, "send_buffer_full"));
+                            break;
+                        }
                     }
-                    Err(error) => eprintln!("error while reading user input: {error}"),
                 }
             }
+            total_commands += commands;
+        }
+        self.metrics.commands_per_tick.observe(total_commands as f64);
+        for token in logins {
+            self.join_player(token);
+        }
+    }
+
+    /// Moves a user that logged in to the player it had before a restart or to the player of its team.
+    fn join_player(&mut self, token: Token) {
+        let Some(username) = self.users.get(&token).and_then(|user| user.username.clone()) else { return };
+        let Some(id) = self.reserved.remove(&username).or_else(|| self.team_player(token, &username)) else { return };
+        let user = self.users.get_mut(&token).expect("checked above");
+        if user.id == Some(id) {
+            return;
+        }
+        let previous = user.id.replace(id);
+        user.queue_position = None;
+        user.span.record("player", tracing::field::display(player_char(id)));
+        info!(parent: &user.span, id, "joined player");
+        self.players[usize::from(id)] = Some(user.addr);
+        match previous {
+            Some(previous) => self.release_player(previous),
+            None => self.waiting.retain(|&waiting| waiting != token),
+        }
+    }
+
+    /// The player of the team `username` belongs to, if a teammate other than `token` plays or has a slot reserved.
+    fn team_player(&self, token: Token, username: &str) -> Option<PlayerId> {
+        let teams = &self.config.teams;
+        let team = teams.get(username)?;
+        let teammates = self.users.iter().filter(|&(&other, _)| other != token);
+        let playing = teammates.filter_map(|(_, user)| Some((user.username.as_ref()?, user.id?)));
+        let reserved = self.reserved.iter().map(|(username, &id)| (username, id));
+        playing.chain(reserved).find(|(username, _)| teams.get(*username) == Some(team)).map(|(_, id)| id)
+    }
+
+    /// Frees the slot of a player once no connection controls it anymore.
+    fn release_player(&mut self, id: PlayerId) {
+        if !self.users.values().any(|user| user.id == Some(id)) {
+            self.players[usize::from(id)] = None;
         }
     }
 
     fn place_pieces(&mut self) {
-        for user in &self.users {
-            if let Some((x, y)) = user.next_stone {
-                self.board.try_place(x as u16, y as u16, user.char)
+        let budget = self.config.budget;
+        if let Some(max) = budget {
+            for (saved, slot) in self.budgets.iter_mut().zip(&self.players) {
+                if slot.is_some() {
+                    *saved = (*saved + 1).min(max);
+                }
             }
         }
-        for user in &mut self.users {
-            if let Some((x, y)) = user.next_stone {
-                self.board.resolve_conflict(x, y);
+        self.spawn_newcomers();
+        let mut placed = Vec::new();
+        let mut handicapped = Vec::new();
+        let mut moves = BTreeMap::<PlayerId, usize>::new();
+        for user in self.users.values_mut() {
+            let Some(id) = user.id else { continue };
+            let mut paid = 0;
+            for (x, y) in user.next_stones.drain(..) {
+                // Handicap stones don't count against any budget
+                let free = self.handicaps[usize::from(id)] > 0;
+                if !free {
+                    let moves = moves.entry(id).or_default();
+                    if self.config.team_moves.is_some_and(|max| *moves >= max) {
+                        self.metrics.reject_move("team_budget");
+                        continue;
+                    }
+                    // Without the action budget every connection places a single stone per turn
+                    let saved = &mut self.budgets[usize::from(id)];
+                    if (budget.is_some() && *saved == 0) || (budget.is_none() && paid > 0) {
+                        self.metrics.reject_move("budget");
+                        continue;
+                    }
+                    if budget.is_some() {
+                        *saved -= 1;
+                    }
+                    paid += 1;
+                    *moves += 1;
+                }
+                match self.board.try_place(x, y, id, self.config.suicide) {
+                    Ok(()) => {
+                        placed.push((id, x, y));
+                        if free {
+                            self.handicaps[usize::from(id)] -= 1;
+                            handicapped.push((id, x, y));
+                        }
+                    }
+                    Err(rejection) => self.metrics.reject_move(rejection.reason()),
+                }
+            }
+        }
+        for &(_, x, y) in &placed {
+            if self.board.is_contested(x, y) {
+                self.metrics.reject_move(Rejection::Contested.reason());
+            }
+        }
+        let mut placed = self.board.place_stones(&placed);
+        if self.config.suicide == Suicide::After {
+            for _ in 0..self.board.take_back_suicides(&mut placed) {
+                self.metrics.reject_move(Rejection::Suicide.reason());
             }
         }
-        for user in &mut self.users {
-            if let Some((x, y)) = user.next_stone.take() {
-                self.board.kill_neighbors(x, y);
+        handicapped.retain(|stone| placed.contains(stone));
+        for (id, x, y) in handicapped {
+            self.board.protect(x, y, id, self.config.protection);
+        }
+        let captures = self.board.capture(&placed, self.config.capture, self.config.convert_neutral);
+        self.metrics.captured_stones += captures.stones as u64;
+        self.metrics.self_captured_stones += captures.self_captured as u64;
+        if let Some(max) = budget {
+            for (id, captured) in captures.credit {
+                let saved = &mut self.budgets[usize::from(id)];
+                *saved = (*saved + captured * self.config.capture_bonus).min(max);
             }
         }
+        self.metrics.captured_stones += self.board.expire_protection() as u64;
+        self.board.age_stones();
+        if let Some(after) = self.config.decay {
+            let decay = Decay {
+                after,
+                liberties: self.config.decay_liberties,
+                size: self.config.decay_size,
+                neutral: self.config.decay_neutral,
+            };
+            self.metrics.decayed_stones += self.board.decay(decay) as u64;
+        }
+        self.shrink_board();
+        self.history.record(&self.board);
+        self.grow_board();
     }
 
-    pub(crate) fn alloc_char(&mut self, addr: SocketAddr) -> Option<u8> {
-        let pos = self.chars.iter().position(Option::is_none)?;
-        self.chars[pos] = Some(addr);
-        Some(pos as u8 + b'A')
+    /// Keeps a zone of the board free for every player that got a slot since the last turn and tells its connections.
+    fn spawn_newcomers(&mut self) {
+        let Some(size) = self.config.spawn_zone else { return };
+        for id in std::mem::take(&mut self.newcomers) {
+            // Players joining their team or their reserved slot gave the new one up again
+            if self.players[usize::from(id)].is_none() {
+                continue;
+            }
+            let Some(corner) = self.board.spawn_zone(size) else {
+                warn!(id, size, "no room for a spawn zone");
+                continue;
+            };
+            self.board.protect_zone(corner, size, id, self.config.protection);
+            let line = format!("SPAWN {} {} {size} {}\n", corner.0, corner.1, self.config.protection);
+            for (&token, user) in self.users.iter_mut().filter(|(_, user)| user.id == Some(id)) {
+                if user.send(line.as_bytes()).is_err() {
+                    self.disconnected.push((token, "send_buffer_full"));
+                }
+            }
+        }
+    }
+
+    /// Closes the next ring of the board every `--shrink` turns.
+    fn shrink_board(&mut self) {
+        let Some(turns) = self.config.shrink else { return };
+        if (self.history.turn() + 1).is_multiple_of(turns) {
+            if let Some(removed) = self.board.shrink() {
+                info!(removed, "board shrank");
+            }
+        }
     }
 
-    fn remove_user(&mut self, addr: SocketAddr) {
-        eprintln!("Removing user {}", addr);
-        if let Some(pos) = self.users.iter().position(|u| u.addr == addr) {
-            self.users.swap_remove(pos);
+    /// Grows the board once it is filled up to `--grow` percent.
+    /// The changed width and height invalidate all deltas, so everybody is sent a keyframe.
+    fn grow_board(&mut self) {
+        let Some(percent) = self.config.grow else { return };
+        if self.board.fill_percent() >= percent && self.board.grow(self.config.grow_step, self.config.max_size) {
+            info!(width = self.board.width, height = self.board.height, "board grew");
+            self.history.reset(&self.board);
         }
-        if let Some(value) = self.chars.iter_mut().find(|x| **x == Some(addr)) {
-            std::mem::take(value);
+    }
+
+    pub(crate) fn alloc_player(&mut self, addr: SocketAddr) -> Option<PlayerId> {
+        let reserved = |pos: usize| self.reserved.values().any(|&id| usize::from(id) == pos);
+        let pos = (0..self.players.len()).find(|&pos| self.players[pos].is_none() && !reserved(pos))?;
+        self.players[pos] = Some(addr);
+        self.budgets[pos] = 0;
+        self.handicaps[pos] = self.config.handicap;
+        if self.config.spawn_zone.is_some() {
+            self.newcomers.insert(pos as PlayerId);
+        }
+        PlayerId::try_from(pos).ok()
+    }
+
+    fn remove_user(&mut self, token: Token, reason: &'static str) {
+        let Some(mut user) = self.users.remove(&token) else { return };
+        info!(parent: &user.span, reason, "removing user");
+        self.metrics.dropped(reason);
+        // Last chance to deliver an error explaining why the connection is closed
+        let _ = user.flush();
+        match user.id {
+            Some(id) => self.release_player(id),
+            None => self.waiting.retain(|&waiting| waiting != token),
         }
     }
 
     fn remove_disconnected_users(&mut self) {
-        for addr in std::mem::take(&mut self.disconnected) {
-            self.remove_user(addr);
+        for (token, reason) in std::mem::take(&mut self.disconnected) {
+            self.remove_user(token, reason);
         }
     }
 
-    fn send_frontend(&mut self, msg: String) {
-        let Some(ref mut frontend) = self.frontend else { return };
-        let message = tungstenite::Message::text(msg);
-        match frontend.send(message) {
-            Err(tungstenite::Error::Io(e))
-                if matches!(e.kind(), ErrorKind::ConnectionAborted | ErrorKind::BrokenPipe) => {}
-            Err(e) => {
-                self.frontend = None;
-                eprintln!("Error while sending {e}");
+    /// Gives free player slots to waiting connections and tells the ones that are still waiting their position.
+    fn admit_waiting(&mut self) {
+        while let Some(&token) = self.waiting.front() {
+            let Some(addr) = self.users.get(&token).map(|user| user.addr) else {
+                self.waiting.pop_front();
+                continue;
+            };
+            let Some(id) = self.alloc_player(addr) else { break };
+            self.waiting.pop_front();
+            let user = self.users.get_mut(&token).expect("checked above");
+            user.id = Some(id);
+            user.queue_position = None;
+            user.span.record("player", tracing::field::display(player_char(id)));
+            info!(parent: &user.span, id, "admitted");
+            // A teammate may have started playing while this connection waited
+            self.join_player(token);
+        }
+        for (position, token) in self.waiting.iter().enumerate() {
+            let Some(user) = self.users.get_mut(token) else { continue };
+            let position = position + 1;
+            if user.queue_position != Some(position) {
+                user.queue_position = Some(position);
+                if user.send(format!("QUEUED {position}\n").as_bytes()).is_err() {
+                    self.disconnected.push((*token, "send_buffer_full"));
+                }
             }
-            _ => (),
         }
     }
 
-    fn update_frontend(&mut self) {
-        self.send_frontend(FrontendMessage::Board(&self.board).to_string());
+    /// Queues the board for every frontend, applying the drop policy to frontends that can't keep up.
+    fn update_frontends(&mut self) {
+        let turn = self.history.turn();
+        let mut dropped = Vec::new();
+        for (&token, frontend) in self.frontends.iter_mut() {
+            if frontend.is_congested() {
+                match frontend.drop_policy {
+                    DropPolicy::Skip => frontend.skip_queued(),
+                    DropPolicy::Disconnect => {
+                        dropped.push(token);
+                        continue;
+                    }
+                }
+            }
+            let update = frontend_update(&self.board, &self.history, frontend);
+            frontend.queue(update);
+            // The websocket has no way to acknowledge turns, so every queued update counts as acknowledged
+            frontend.delta.acked_turn = Some(turn);
+        }
+        for token in dropped {
+            if let Some(frontend) = self.frontends.remove(&token) {
+                self.metrics.dropped("frontend_congested");
+                warn!(addr = %frontend.addr, "dropping frontend: too many queued updates");
+            }
+        }
+    }
+
+    /// The score of every player on the board or in the game, best first. A player scores a point per stone.
+    fn scores(&self) -> Vec<(PlayerId, usize)> {
+        let mut scores = self.board.stones();
+        for id in self.users.values().filter_map(|user| user.id) {
+            scores.entry(id).or_default();
+        }
+        let mut scores: Vec<_> = scores.into_iter().collect();
+        scores.sort_by_key(|&(id, score)| (std::cmp::Reverse(score), id));
+        scores
+    }
+
+    /// Ends the game for good, telling every bot and frontend the final scores.
+    fn end(&mut self) {
+        let scores = self.scores();
+        info!(?scores, "game over");
+        for user in self.users.values_mut() {
+            let end = End { scores: &scores, encoding: user.encoding };
+            // Output that doesn't fit is lost anyway, the connection is closed next
+            let _ = user.send(format!("{end}\n").as_bytes());
+        }
+        for frontend in self.frontends.values_mut() {
+            let end = End { scores: &scores, encoding: frontend.encoding };
+            frontend.queue(vec![Message::text(FrontendMessage::End(end).to_string())]);
+        }
     }
 
     fn broadcast_gamestate(&mut self) {
-        self.board.print_board();
-        let state = self.board.serialize();
-        for user in self.users.iter_mut() {
-            match writeln!(
-                user.stream,
-                "BOARD {} {} {} {}",
-                user.char, self.board.width, self.board.height, state
-            ) {
-                Err(e) if e.kind() == ErrorKind::ConnectionAborted => (),
-                Err(e) if e.kind() == ErrorKind::BrokenPipe => (),
-                Err(e) if matches!(e.kind(), ErrorKind::ConnectionAborted | ErrorKind::BrokenPipe) => {
-                    self.disconnected.push(user.addr);
+        // Only rendered if enabled, with `--log goto::board=debug`
+        debug!(target: "goto::board", turn = self.history.turn(), "\n{}", self.board.render_liberties());
+        let state = OnceCell::new();
+        let binary = OnceCell::new();
+        let ages = OnceCell::new();
+        let turn = self.history.turn();
+        for (&token, user) in self.users.iter_mut() {
+            let (width, height) = (self.board.width, self.board.height);
+            // Deltas don't know what a player can see, so the fog of war always sends full boards
+            let view = self.config.fog.map(|radius| self.board.view(user.id, radius));
+            let update = if view.is_some() { None } else { self.history.update_for(&user.delta) };
+            let mut frame = match (update, user.encoding) {
+                (Some((base, changes)), encoding) => {
+                    let delta = Delta {
+                        board: &self.board,
+                        turn,
+                        base,
+                        changes: &changes,
+                        encoding,
+                    };
+                    format!("{delta}\n").into_bytes()
                 }
-                Err(e) => {
-                    eprintln!("Error while sending {e}");
+                (None, Encoding::Binary) => {
+                    let payload = match &view {
+                        Some(view) => &view.serialize_binary(),
+                        None => binary.get_or_init(|| self.board.serialize_binary()),
+                    };
+                    let you = user.id.map_or_else(|| "-".to_owned(), |id| id.to_string());
+                    let mut frame = format!("BINBOARD {you} {turn} {}\n", payload.len()).into_bytes();
+                    frame.extend_from_slice(payload);
+                    frame
+                }
+                (None, Encoding::Text) => {
+                    let state = match &view {
+                        Some(view) => &view.serialize(),
+                        None => state.get_or_init(|| self.board.serialize()),
+                    };
+                    let you = user.id.map_or('-', player_char);
+                    if user.delta.enabled {
+                        format!("BOARD {you} {width} {height} {state} {turn}\n").into_bytes()
+                    } else {
+                        format!("BOARD {you} {width} {height} {state}\n").into_bytes()
+                    }
                 }
-                _ => (),
+            };
+            if user.ages {
+                let ages = match &view {
+                    Some(view) => &view.serialize_ages(),
+                    None => ages.get_or_init(|| self.board.serialize_ages()),
+                };
+                frame.splice(0..0, format!("AGES {ages}\n").into_bytes());
+            }
+            if let (Some(_), Some(id)) = (self.config.budget, user.id) {
+                let budget = format!("BUDGET {}\n", self.budgets[usize::from(id)]);
+                frame.splice(0..0, budget.into_bytes());
+            }
+            if let Err(e) = user.send(&frame) {
+                warn!(parent: &user.span, "dropping: {e}");
+                self.disconnected.push((token, "send_buffer_full"));
             }
         }
     }
 }
 
+/// Sets up logging to stderr. Events are filtered by `--log`, or by `RUST_LOG` if it isn't given.
+fn init_logging(config: &Config) -> Result<(), String> {
+    let filter = match &config.log {
+        Some(directives) => EnvFilter::try_new(directives),
+        None => EnvFilter::try_from_default_env().or_else(|_| EnvFilter::try_new("info")),
+    };
+    let filter = filter.map_err(|e| format!("invalid log filter: {e}"))?;
+    let subscriber = tracing_subscriber::fmt().with_env_filter(filter).with_writer(std::io::stderr);
+    match config.log_format {
+        LogFormat::Text => subscriber.init(),
+        LogFormat::Json => subscriber.json().init(),
+    }
+    Ok(())
+}
+
+/// The messages that bring a frontend up to date with the current turn.
+fn frontend_update(board: &Board, history: &History, frontend: &Frontend) -> Vec<Message> {
+    let turn = history.turn();
+    match (history.update_for(&frontend.delta), frontend.encoding) {
+        (Some((base, changes)), encoding) => vec![Message::text(
+            FrontendMessage::Delta(Delta {
+                board,
+                turn,
+                base,
+                changes: &changes,
+                encoding,
+            })
+            .to_string(),
+        )],
+        (None, Encoding::Binary) => {
+            let payload = board.serialize_binary();
+            let header = FrontendMessage::BinaryBoard(board, turn, payload.len());
+            vec![Message::text(header.to_string()), Message::binary(payload)]
+        }
+        (None, Encoding::Text) if frontend.delta.enabled => {
+            vec![Message::text(FrontendMessage::Keyframe(board, turn).to_string())]
+        }
+        (None, Encoding::Text) => vec![Message::text(FrontendMessage::Board(board).to_string())],
+    }
+}
+
 fn main() -> std::io::Result<()> {
-    let listener = TcpListener::bind("0.0.0.0:1312")?;
-    let ws_listener = TcpListener::bind("0.0.0.0:1213")?;
-    listener.set_nonblocking(true)?;
-    ws_listener.set_nonblocking(true)?;
-    let mut game = GameState::new(15);
-    loop {
-        if let Err(e) = network::accept_new_connections(&listener, &mut game) {
-            eprintln!("Error while accepting a new connection: {e}");
+    let config = match Config::from_args(std::env::args().skip(1)) {
+        Ok(Some(config)) => config,
+        Ok(None) => {
+            print!("{}", config::USAGE);
+            return Ok(());
         }
-        if let Err(e) = network::accept_new_ws(&ws_listener, &mut game) {
-            eprintln!("Error while accepting a new connection: {e}");
+        Err(e) => {
+            eprint!("{e}\n\n{}", config::USAGE);
+            std::process::exit(2);
         }
+    };
+    if let Err(e) = init_logging(&config) {
+        eprintln!("{e}");
+        std::process::exit(2);
+    }
+    let admin_addr = config.admin_token.is_some().then(|| ([0, 0, 0, 0], config.admin_port).into());
+    let metrics_addr = config.metrics_port.map(|port| ([0, 0, 0, 0], port).into());
+    let mut network = Network::bind(
+        ([0, 0, 0, 0], 1312).into(),
+        ([0, 0, 0, 0], 1213).into(),
+        admin_addr,
+        metrics_addr,
+    )?;
+    let resume_from = config.snapshot.clone().filter(|_| config.resume);
+    let mut game = GameState::new(15, config);
+    if let Some(path) = resume_from {
+        let snapshot = std::fs::read_to_string(&path)?;
+        let resumed = game.resume(&snapshot);
+        resumed.map_err(|e| std::io::Error::new(ErrorKind::InvalidData, format!("{}: {e}", path.display())))?;
+        info!(path = %path.display(), turn = game.history.turn(), "resumed from snapshot");
+    }
+    let mut next_tick = Instant::now() + game.tick;
+    let mut last_snapshot = Instant::now();
+    loop {
+        network.poll_until(&mut game, next_tick)?;
+        if network.shutdown_requested() {
+            break;
+        }
+        next_tick += game.tick;
+        let started = Instant::now();
+
+        game.process_admin_input();
         game.process_user_input();
         game.remove_disconnected_users();
+        game.admit_waiting();
+        if !game.paused {
+            if game.running {
+                game.place_pieces();
+            }
+            game.update_frontends();
+            game.broadcast_gamestate();
+        }
+        if let Some(path) = game.config.snapshot.as_deref() {
+            if last_snapshot.elapsed() >= game.config.snapshot_interval {
+                last_snapshot = Instant::now();
+                if let Err(e) = game.save_snapshot(path) {
+                    error!(path = %path.display(), "failed to save snapshot: {e}");
+                }
+            }
+        }
+        network.flush(&mut game);
+        game.metrics.tick_duration.observe(started.elapsed().as_secs_f64());
+    }
+
+    game.end();
+    if let Some(path) = game.config.snapshot.as_deref() {
+        match game.save_snapshot(path) {
+            Ok(()) => info!(path = %path.display(), "saved snapshot"),
+            Err(e) => error!(path = %path.display(), "failed to save snapshot: {e}"),
+        }
+    }
+    network.close(&mut game);
+    info!("shut down");
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Connects a bot logged in as `username`, the way the network and the `LOGIN` command do.
+    pub(crate) fn connect(game: &mut GameState, username: &str) -> Token {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("free port");
+        let stream = std::net::TcpStream::connect(listener.local_addr().expect("bound")).expect("connected");
+        stream.set_nonblocking(true).expect("nonblocking");
+        let addr = stream.local_addr().expect("connected");
+        let token = Token(game.users.keys().map(|token| token.0 + 1).max().unwrap_or_default());
+        let id = if game.waiting.is_empty() { game.alloc_player(addr) } else { None };
+        if id.is_none() {
+            game.waiting.push_back(token);
+        }
+        let mut user = Connection::new(mio::net::TcpStream::from_std(stream), addr, id, tracing::Span::none());
+        user.username = Some(username.to_owned());
+        game.users.insert(token, user);
+        game.join_player(token);
+        token
+    }
+
+    /// A game in which alice and bob play as team red.
+    pub(crate) fn team_game(team_moves: Option<usize>) -> GameState {
+        let teams = [("alice", "red"), ("bob", "red")].map(|(username, team)| (username.to_owned(), team.to_owned()));
+        GameState::new(5, Config { teams: BTreeMap::from(teams), team_moves, ..Config::default() })
+    }
+
+    #[test]
+    fn teammates_share_a_player() {
+        let mut game = team_game(None);
+        let alice = connect(&mut game, "alice");
+        let bob = connect(&mut game, "bob");
+        let carol = connect(&mut game, "carol");
+        assert_eq!(game.users[&alice].id, Some(0));
+        assert_eq!(game.users[&bob].id, Some(0));
+        // The slot bob got before logging in was given up again
+        assert_eq!(game.users[&carol].id, Some(1));
+        assert!(game.players[2].is_none());
+    }
+
+    #[test]
+    fn the_slot_of_a_team_is_kept_until_the_last_teammate_leaves() {
+        let mut game = team_game(None);
+        let alice = connect(&mut game, "alice");
+        let bob = connect(&mut game, "bob");
+        game.remove_user(alice, "connection_lost");
+        assert!(game.players[0].is_some());
+        let alice = connect(&mut game, "alice");
+        assert_eq!(game.users[&alice].id, Some(0));
+        game.remove_user(alice, "connection_lost");
+        game.remove_user(bob, "connection_lost");
+        assert!(game.players[0].is_none());
+    }
+
+    #[test]
+    fn team_moves_limit_the_stones_of_a_team() {
+        for (team_moves, stones) in [(None, 2), (Some(1), 1), (Some(3), 2)] {
+            let mut game = team_game(team_moves);
+            let alice = connect(&mut game, "alice");
+            let bob = connect(&mut game, "bob");
+            game.users.get_mut(&alice).expect("connected").next_stones = vec![(0, 0)];
+            game.users.get_mut(&bob).expect("connected").next_stones = vec![(2, 2)];
+            game.place_pieces();
+            assert_eq!(game.board.stones(), BTreeMap::from([(0, stones)]), "{team_moves:?}");
+            let rejected = game.metrics.rejected_moves.get("team_budget").copied().unwrap_or_default();
+            assert_eq!(rejected, 2 - stones as u64);
+        }
+    }
+
+    /// A game with the action budget in which alice plays player 0.
+    fn budget_game(max: usize, capture_bonus: usize) -> (GameState, Token) {
+        let mut game = GameState::new(5, Config { budget: Some(max), capture_bonus, ..Config::default() });
+        let alice = connect(&mut game, "alice");
+        (game, alice)
+    }
+
+    fn play(game: &mut GameState, token: Token, stones: &[(u16, u16)]) {
+        game.users.get_mut(&token).expect("connected").next_stones = stones.to_vec();
         game.place_pieces();
-        game.update_frontend();
-        game.broadcast_gamestate();
+    }
+
+    #[test]
+    fn budgets_are_capped() {
+        let (mut game, alice) = budget_game(3, 1);
+        for _ in 0..5 {
+            play(&mut game, alice, &[]);
+        }
+        assert_eq!(game.budgets[0], 3);
+        play(&mut game, alice, &[(0, 0), (1, 0), (2, 0), (3, 0)]);
+        assert_eq!(game.board.stones(), BTreeMap::from([(0, 3)]));
+        assert_eq!(game.budgets[0], 0);
+        assert_eq!(game.metrics.rejected_moves.get("budget"), Some(&1));
+    }
 
-        std::thread::sleep(Duration::from_millis(100));
+    #[test]
+    fn rejected_moves_still_spend_their_stone() {
+        let (mut game, alice) = budget_game(3, 1);
+        game.board.try_place(1, 1, 1, Suicide::Before).expect("legal move");
+        game.board.place_stones(&[(1, 1, 1)]);
+        play(&mut game, alice, &[]);
+        play(&mut game, alice, &[]);
+        // The budget grows to 3 this turn, the occupied field and the one off the board cost one stone each
+        play(&mut game, alice, &[(1, 1), (9, 9)]);
+        assert_eq!(game.board.stones(), BTreeMap::from([(1, 1)]));
+        assert_eq!(game.budgets[0], 1);
+        assert_eq!(game.metrics.rejected_moves.get("occupied"), Some(&1));
+        assert_eq!(game.metrics.rejected_moves.get("out_of_bounds"), Some(&1));
+        assert_eq!(game.metrics.rejected_moves.get("budget"), None);
+    }
+
+    #[test]
+    fn captures_earn_the_capture_bonus() {
+        let (mut game, alice) = budget_game(4, 3);
+        for (id, x, y) in [(1, 0, 0), (0, 1, 0)] {
+            game.board.try_place(x, y, id, Suicide::Before).expect("legal move");
+            game.board.place_stones(&[(id, x, y)]);
+        }
+        play(&mut game, alice, &[(0, 1)]);
+        assert_eq!(game.metrics.captured_stones, 1);
+        assert_eq!(game.budgets[0], 3);
+        // The bonus doesn't go beyond the maximum either
+        game.board.try_place(4, 4, 1, Suicide::Before).expect("legal move");
+        game.board.place_stones(&[(1, 4, 4)]);
+        play(&mut game, alice, &[(3, 4), (4, 3)]);
+        assert_eq!(game.budgets[0], 4);
     }
 }
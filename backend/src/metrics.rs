@@ -0,0 +1,146 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::GameState;
+
+/// Upper bounds of the tick duration buckets, in seconds.
+const TICK_DURATION_BUCKETS: &[f64] = &[0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 1.0];
+/// Upper bounds of the commands per tick buckets.
+const COMMANDS_BUCKETS: &[f64] = &[0.0, 1.0, 10.0, 50.0, 100.0, 500.0, 1000.0, 5000.0];
+
+/// Counters collected while the server runs, exposed in the Prometheus text format by [`GameState::render_metrics`].
+#[derive(Debug)]
+pub(crate) struct Metrics {
+    pub(crate) tick_duration: Histogram,
+    pub(crate) commands_per_tick: Histogram,
+    /// Moves that didn't end up on the board, by reason.
+    pub(crate) rejected_moves: BTreeMap<&'static str, u64>,
+    pub(crate) captured_stones: u64,
+    pub(crate) decayed_stones: u64,
+    pub(crate) self_captured_stones: u64,
+    /// Bytes written to sockets, by kind of connection.
+    pub(crate) bytes_sent: BTreeMap<&'static str, u64>,
+    /// Connections closed by the server or lost, by reason.
+    pub(crate) dropped_connections: BTreeMap<&'static str, u64>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics {
+            tick_duration: Histogram::new(TICK_DURATION_BUCKETS),
+            commands_per_tick: Histogram::new(COMMANDS_BUCKETS),
+            rejected_moves: BTreeMap::new(),
+            captured_stones: 0,
+            decayed_stones: 0,
+            self_captured_stones: 0,
+            bytes_sent: BTreeMap::new(),
+            dropped_connections: BTreeMap::new(),
+        }
+    }
+}
+
+impl Metrics {
+    pub(crate) fn reject_move(&mut self, reason: &'static str) {
+        *self.rejected_moves.entry(reason).or_default() += 1;
+    }
+
+    pub(crate) fn sent(&mut self, to: &'static str, bytes: usize) {
+        *self.bytes_sent.entry(to).or_default() += bytes as u64;
+    }
+
+    pub(crate) fn dropped(&mut self, reason: &'static str) {
+        *self.dropped_connections.entry(reason).or_default() += 1;
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Histogram {
+    bounds: &'static [f64],
+    /// Observations per bucket, not cumulative. The last entry counts everything above the highest bound.
+    counts: Vec<u64>,
+    sum: f64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Histogram {
+            bounds,
+            counts: vec![0; bounds.len() + 1],
+            sum: 0.0,
+        }
+    }
+
+    pub(crate) fn observe(&mut self, value: f64) {
+        let bucket = self.bounds.iter().position(|&bound| value <= bound).unwrap_or(self.bounds.len());
+        self.counts[bucket] += 1;
+        self.sum += value;
+    }
+
+    fn render(&self, out: &mut String, name: &str) {
+        let mut cumulative = 0;
+        for (bound, count) in self.bounds.iter().zip(&self.counts) {
+            cumulative += count;
+            let _ = writeln!(out, "{name}_bucket{{le=\"{bound}\"}} {cumulative}");
+        }
+        cumulative += self.counts[self.bounds.len()];
+        let _ = writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {cumulative}");
+        let _ = writeln!(out, "{name}_sum {}", self.sum);
+        let _ = writeln!(out, "{name}_count {cumulative}");
+    }
+}
+
+/// Writes the `# HELP` and `# TYPE` header of a metric.
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+fn labeled(out: &mut String, name: &str, label: &str, values: &BTreeMap<&'static str, u64>) {
+    for (value, count) in values {
+        let _ = writeln!(out, "{name}{{{label}=\"{value}\"}} {count}");
+    }
+}
+
+impl GameState {
+    /// Renders the current metrics in the Prometheus text exposition format.
+    pub(crate) fn render_metrics(&self) -> String {
+        let metrics = &self.metrics;
+        let mut out = String::new();
+        let playing = self.users.values().filter(|user| user.id.is_some()).count();
+
+        header(&mut out, "goto_bots", "gauge", "Connected bots, by whether they play or wait for a slot.");
+        let _ = writeln!(out, "goto_bots{{state=\"playing\"}} {playing}");
+        let _ = writeln!(out, "goto_bots{{state=\"waiting\"}} {}", self.users.len() - playing);
+
+        header(&mut out, "goto_spectators", "gauge", "Connected websocket frontends.");
+        let _ = writeln!(out, "goto_spectators {}", self.frontends.len());
+
+        header(&mut out, "goto_turn", "gauge", "The current turn.");
+        let _ = writeln!(out, "goto_turn {}", self.history.turn());
+
+        header(&mut out, "goto_tick_duration_seconds", "histogram", "Time spent processing a tick.");
+        metrics.tick_duration.render(&mut out, "goto_tick_duration_seconds");
+
+        header(&mut out, "goto_commands_per_tick", "histogram", "Commands received from all bots per tick.");
+        metrics.commands_per_tick.render(&mut out, "goto_commands_per_tick");
+
+        header(&mut out, "goto_rejected_moves_total", "counter", "Moves that weren't placed, by reason.");
+        labeled(&mut out, "goto_rejected_moves_total", "reason", &metrics.rejected_moves);
+
+        header(&mut out, "goto_captured_stones_total", "counter", "Stones removed from the board by captures.");
+        let _ = writeln!(out, "goto_captured_stones_total {}", metrics.captured_stones);
+
+        header(&mut out, "goto_decayed_stones_total", "counter", "Old stones removed from the board.");
+        let _ = writeln!(out, "goto_decayed_stones_total {}", metrics.decayed_stones);
+
+        header(&mut out, "goto_self_captured_stones_total", "counter", "Stones removed by self-captures.");
+        let _ = writeln!(out, "goto_self_captured_stones_total {}", metrics.self_captured_stones);
+
+        header(&mut out, "goto_bytes_sent_total", "counter", "Bytes written to sockets, by kind of connection.");
+        labeled(&mut out, "goto_bytes_sent_total", "to", &metrics.bytes_sent);
+
+        header(&mut out, "goto_dropped_connections_total", "counter", "Connections that were closed or lost, by reason.");
+        labeled(&mut out, "goto_dropped_connections_total", "reason", &metrics.dropped_connections);
+        out
+    }
+}
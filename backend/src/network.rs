@@ -1,26 +1,111 @@
 use std::{
-    collections::{hash_map::Entry, HashMap},
+    cell::Cell,
+    collections::{hash_map::Entry, HashMap, VecDeque},
     fmt::Display,
-    hash::Hasher,
-    io::{ErrorKind, Read},
-    net::{SocketAddr, TcpListener, TcpStream},
+    io::{ErrorKind, Read, Write},
+    net::{Shutdown, SocketAddr},
+    rc::Rc,
     str::FromStr,
+    time::{Duration, Instant},
 };
 
+use mio::net::{TcpListener, TcpStream};
+use mio::{Events, Interest, Poll, Token};
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook_mio::v1_0::Signals;
+use tungstenite::handshake::server::{Callback, ErrorResponse, Request, Response, ServerHandshake};
+use tungstenite::handshake::{HandshakeError, MidHandshake};
+use tungstenite::http::StatusCode;
+use tungstenite::{Message, WebSocket};
+use sha2::{Digest, Sha256};
+use tracing::{debug, error, field, info, info_span, warn, Span};
+
 use crate::{
-    game::{Board, Position},
+    game::{player_char, Board, DeltaState, Encoding, PlayerId, Position, Query},
     GameState,
 };
 
+use self::framing::LineBuffer;
+use self::http::Scrape;
+
+mod framing;
+mod http;
+
+const LISTENER: Token = Token(0);
+const WS_LISTENER: Token = Token(1);
+const ADMIN_LISTENER: Token = Token(2);
+const METRICS_LISTENER: Token = Token(3);
+const SIGNALS: Token = Token(4);
+
+/// Connections that have more output queued than this are too slow to keep up and get dropped.
+const MAX_SEND_BUFFER: usize = 1 << 22;
+/// Frontends that have this many updates queued are too slow to keep up, see [`DropPolicy`].
+const MAX_QUEUED_UPDATES: usize = 8;
+/// Websocket handshakes that take longer than this are aborted.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+/// The window `max_bytes_per_second` is counted in.
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+/// How long a shutdown waits for connections to take their last messages and close.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
 #[derive(Debug)]
 pub enum Error {
     InvalidArgument,
     UnknownCommand,
+    InvalidCredentials,
+    NotPlaying,
+    Unauthorized,
+    GameStopped,
+    InvalidMap(&'static str),
     ConnectionLost,
     WouldBlock,
-    GameFull,
+    LineTooLong,
+    SendBufferFull,
+    /// Commands beyond the per tick limit, with the number of lines that were ignored.
+    TooManyCommands(usize),
+    TooManyQueries,
+    RateLimited,
+    TooManyInvalidCommands,
+    TooManyConnections,
+    Kicked,
+    Banned,
     IO(std::io::Error),
     Utf8(std::str::Utf8Error),
+    WebSocket(Box<tungstenite::Error>),
+}
+
+impl Error {
+    /// The code identifying the error in `ERROR` responses, documented in the README.
+    pub(crate) fn code(&self) -> u16 {
+        match self {
+            Error::UnknownCommand => 100,
+            Error::InvalidArgument => 101,
+            Error::Utf8(_) => 102,
+            Error::LineTooLong => 103,
+            Error::InvalidCredentials => 104,
+            Error::NotPlaying => 105,
+            Error::Unauthorized => 106,
+            Error::GameStopped => 107,
+            Error::InvalidMap(_) => 108,
+            Error::TooManyCommands(_) => 200,
+            Error::RateLimited => 201,
+            Error::TooManyInvalidCommands => 202,
+            Error::TooManyConnections => 203,
+            Error::SendBufferFull => 205,
+            Error::Kicked => 206,
+            Error::Banned => 207,
+            Error::TooManyQueries => 208,
+            Error::ConnectionLost => 300,
+            Error::WouldBlock => 301,
+            Error::IO(_) => 302,
+            Error::WebSocket(_) => 303,
+        }
+    }
+
+    /// The `ERROR <code> <message>` line telling a bot about the error.
+    pub(crate) fn response(&self) -> String {
+        format!("ERROR {} {self}\n", self.code())
+    }
 }
 
 impl From<std::io::Error> for Error {
@@ -32,26 +117,129 @@ impl From<std::io::Error> for Error {
         }
     }
 }
+impl From<tungstenite::Error> for Error {
+    fn from(value: tungstenite::Error) -> Self {
+        match value {
+            tungstenite::Error::Io(e) => e.into(),
+            tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed => Error::ConnectionLost,
+            e => Error::WebSocket(Box::new(e)),
+        }
+    }
+}
 impl From<std::str::Utf8Error> for Error {
     fn from(value: std::str::Utf8Error) -> Self {
         Error::Utf8(value)
     }
 }
-pub(crate) type Color = u32;
 
 #[derive(Debug)]
 pub(crate) struct Connection {
     pub(crate) addr: SocketAddr,
     pub(crate) username: Option<String>,
-    color: Color,
-    pub(crate) char: u8,
-    pub(crate) stream: TcpStream,
-    pub(crate) next_stone: Option<Position>,
+    /// The player this connection controls, `None` while it is waiting for a free slot and spectating.
+    pub(crate) id: Option<PlayerId>,
+    /// The last position in the waiting queue that was sent to the connection.
+    pub(crate) queue_position: Option<usize>,
+    stream: TcpStream,
+    pub(crate) incoming: LineBuffer,
+    outgoing: Vec<u8>,
+    /// The stones to place this turn, in the order they were sent.
+    pub(crate) next_stones: Vec<Position>,
+    pub(crate) delta: DeltaState,
+    pub(crate) encoding: Encoding,
+    /// Whether the ages of the stones are sent with every board.
+    pub(crate) ages: bool,
+    /// Invalid commands received so far, the connection is kicked once there are too many.
+    pub(crate) invalid_commands: usize,
+    rate: RateWindow,
+    /// Whether reading was paused because the connection sent too much, see [`Connection::receive`].
+    throttled: bool,
+    /// Context for log events about this connection: its address, username and player character.
+    pub(crate) span: Span,
+}
+
+#[derive(Debug)]
+pub(crate) struct Frontend {
+    pub(crate) addr: SocketAddr,
+    socket: WebSocket<TcpStream>,
+    /// Updates waiting to be written, each consisting of one or more messages.
+    queue: VecDeque<Vec<Message>>,
+    pub(crate) delta: DeltaState,
+    pub(crate) encoding: Encoding,
+    pub(crate) drop_policy: DropPolicy,
+    rate: RateWindow,
+}
+
+/// A connection to the admin port, see [`crate::admin`].
+#[derive(Debug)]
+pub(crate) struct Admin {
+    pub(crate) addr: SocketAddr,
+    stream: TcpStream,
+    pub(crate) incoming: LineBuffer,
+    outgoing: Vec<u8>,
+    pub(crate) authenticated: bool,
+    rate: RateWindow,
+}
+
+/// The bytes a connection sent in the current [`RATE_WINDOW`], see `--max-bytes-per-second`.
+#[derive(Debug)]
+struct RateWindow {
+    received: usize,
+    start: Instant,
+}
+
+impl Default for RateWindow {
+    fn default() -> Self {
+        RateWindow { received: 0, start: Instant::now() }
+    }
+}
+
+impl RateWindow {
+    /// The bytes the connection may still send in the current window, starting a new window when it is over.
+    fn budget(&mut self, max_bytes_per_second: usize) -> usize {
+        if self.start.elapsed() >= RATE_WINDOW {
+            self.start = Instant::now();
+            self.received = 0;
+        }
+        max_bytes_per_second.saturating_sub(self.received)
+    }
+}
+
+/// What to do with a frontend that doesn't read its updates fast enough.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum DropPolicy {
+    /// Throw away the queued updates and continue with a full board.
+    #[default]
+    Skip,
+    /// Close the connection.
+    Disconnect,
 }
 
 impl Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{self:?}")
+        match self {
+            Error::InvalidArgument => write!(f, "invalid argument"),
+            Error::UnknownCommand => write!(f, "unknown command"),
+            Error::InvalidCredentials => write!(f, "invalid credentials"),
+            Error::NotPlaying => write!(f, "not playing, wait for a free slot"),
+            Error::Unauthorized => write!(f, "not authenticated"),
+            Error::GameStopped => write!(f, "the game is stopped"),
+            Error::InvalidMap(reason) => write!(f, "invalid map: {reason}"),
+            Error::ConnectionLost => write!(f, "connection lost"),
+            Error::WouldBlock => write!(f, "operation would block"),
+            Error::LineTooLong => write!(f, "line too long"),
+            Error::SendBufferFull => write!(f, "too much output queued"),
+            Error::TooManyCommands(ignored) => write!(f, "too many commands, ignored {ignored}"),
+            Error::RateLimited => write!(f, "rate limit exceeded"),
+            Error::TooManyInvalidCommands => write!(f, "too many invalid commands"),
+            Error::TooManyConnections => write!(f, "too many connections"),
+            Error::Kicked => write!(f, "kicked by an admin"),
+            Error::Banned => write!(f, "banned by an admin"),
+            Error::TooManyQueries => write!(f, "too many queries"),
+            Error::IO(e) => write!(f, "io error: {e}"),
+            Error::Utf8(e) => write!(f, "invalid utf-8: {e}"),
+            Error::WebSocket(e) => write!(f, "websocket error: {e}"),
+        }
     }
 }
 
@@ -59,14 +247,29 @@ impl Display for Error {
 pub(crate) enum Command {
     Login(String, String),
     Put(Position),
+    /// Several stones at once, for the action budget mode.
+    Puts(Vec<Position>),
+    Delta(bool),
+    Ack(u64),
+    Encoding(Encoding),
+    Ages(bool),
+    /// A question about the board, answered right away.
+    Query(Query),
 }
 impl FromStr for Command {
     type Err = Error;
 
     fn from_str(line: &str) -> Result<Self, Self::Err> {
+        if let Some(coordinates) = line.strip_prefix("PUTS ") {
+            let coordinates = coordinates.split(' ').map(|c| c.parse().map_err(|_| Error::InvalidArgument));
+            let coordinates: Vec<u16> = coordinates.collect::<Result<_, _>>()?;
+            if !coordinates.len().is_multiple_of(2) {
+                return Err(Error::InvalidArgument);
+            }
+            return Ok(Command::Puts(coordinates.chunks(2).map(|pair| (pair[0], pair[1])).collect()));
+        }
         let mut args = line.splitn(3, ' ');
         let args = std::array::from_fn(|_| args.next().unwrap_or_default());
-        dbg!(&args);
         match args {
             ["LOGIN", user, password] => Ok(Command::Login(user.to_owned(), password.to_owned())),
             ["PUT", x, y] => {
@@ -74,80 +277,759 @@ impl FromStr for Command {
                 let y: u16 = y.parse().map_err(|_| Error::InvalidArgument)?;
                 Ok(Command::Put((x, y)))
             }
+            ["PUTS", _, _] => Err(Error::InvalidArgument),
+            ["DELTA", "ON", ""] => Ok(Command::Delta(true)),
+            ["DELTA", "OFF", ""] => Ok(Command::Delta(false)),
+            ["DELTA", _, _] => Err(Error::InvalidArgument),
+            ["ENCODING", "TEXT", ""] => Ok(Command::Encoding(Encoding::Text)),
+            ["ENCODING", "BINARY", ""] => Ok(Command::Encoding(Encoding::Binary)),
+            ["ENCODING", _, _] => Err(Error::InvalidArgument),
+            ["AGES", "ON", ""] => Ok(Command::Ages(true)),
+            ["AGES", "OFF", ""] => Ok(Command::Ages(false)),
+            ["AGES", _, _] => Err(Error::InvalidArgument),
+            ["GROUP", x, y] | ["LIBERTIES", x, y] => {
+                let x: u16 = x.parse().map_err(|_| Error::InvalidArgument)?;
+                let y: u16 = y.parse().map_err(|_| Error::InvalidArgument)?;
+                Ok(Command::Query(if args[0] == "GROUP" { Query::Group((x, y)) } else { Query::Liberties((x, y)) }))
+            }
+            ["TERRITORY", "", ""] => Ok(Command::Query(Query::Territory)),
+            ["LEGAL", "", ""] => Ok(Command::Query(Query::Legal)),
+            ["ACK", turn, ""] => Ok(Command::Ack(turn.parse().map_err(|_| Error::InvalidArgument)?)),
             _ => Err(Error::UnknownCommand),
         }
     }
 }
 
-pub(crate) fn parse_line(
-    stream: &mut TcpStream,
-    parse: impl Fn(&str) -> Result<Command, Error>,
-) -> Result<Command, Error> {
-    let mut buf = [0; 1024];
-    let bytes = stream.peek(&mut buf)?;
-    if bytes == 0 {
-        return Err(Error::ConnectionLost);
+pub(crate) fn parse_line<T>(lines: &mut LineBuffer, parse: impl Fn(&str) -> Result<T, Error>) -> Result<T, Error> {
+    parse(&lines.next_line().ok_or(Error::WouldBlock)??)
+}
+
+impl Connection {
+    /// A new bot connection that hasn't logged in yet, controlling player `id` or waiting for a slot.
+    pub(crate) fn new(stream: TcpStream, addr: SocketAddr, id: Option<PlayerId>, span: Span) -> Self {
+        Connection {
+            addr,
+            username: None,
+            id,
+            queue_position: None,
+            stream,
+            incoming: LineBuffer::default(),
+            outgoing: Vec::new(),
+            next_stones: Vec::new(),
+            delta: DeltaState::default(),
+            encoding: Encoding::default(),
+            ages: false,
+            invalid_commands: 0,
+            rate: RateWindow::default(),
+            throttled: false,
+            span,
+        }
+    }
+
+    /// Reads what is available on the socket into the input buffer, but no more than `max_bytes_per_second`.
+    ///
+    /// Once the limit is reached the rest is left in the socket, so TCP pushes back on the sender,
+    /// and [`Network::flush`] resumes reading in a later window.
+    fn receive(&mut self, max_bytes_per_second: usize) -> Result<(), Error> {
+        let mut chunk = [0; 4096];
+        loop {
+            let budget = self.rate.budget(max_bytes_per_second).min(chunk.len());
+            if budget == 0 {
+                if !self.throttled {
+                    self.throttled = true;
+                    warn!(parent: &self.span, max_bytes_per_second, "throttling");
+                    self.send_error(&Error::RateLimited)?;
+                }
+                return Ok(());
+            }
+            match self.stream.read(&mut chunk[..budget]) {
+                Ok(0) => return Err(Error::ConnectionLost),
+                Ok(n) => {
+                    self.rate.received += n;
+                    self.incoming.extend(&chunk[..n]);
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => (),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    self.throttled = false;
+                    return Ok(());
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Queues an `ERROR` response for the bot.
+    pub(crate) fn send_error(&mut self, error: &Error) -> Result<(), Error> {
+        self.send(error.response().as_bytes())
     }
-    let pos = buf[0..bytes]
-        .iter()
-        .position(|a| a == &b'\n')
-        .ok_or(Error::WouldBlock)?;
-    stream.read_exact(&mut buf[0..=pos])?;
-    let str = std::str::from_utf8(&buf[0..pos])?;
-    if pos > 0 {
-        parse(str)
-    } else {
-        Err(Error::WouldBlock)
+
+    /// Queues data to be sent with the next flush.
+    pub(crate) fn send(&mut self, data: &[u8]) -> Result<(), Error> {
+        if self.outgoing.len() + data.len() > MAX_SEND_BUFFER {
+            return Err(Error::SendBufferFull);
+        }
+        self.outgoing.extend_from_slice(data);
+        Ok(())
+    }
+
+    /// Writes as much of the queued output as the socket accepts without blocking, returning the bytes written.
+    pub(crate) fn flush(&mut self) -> Result<usize, Error> {
+        write_queued(&mut self.stream, &mut self.outgoing)
+    }
+
+    /// Takes a step towards closing the connection, see [`close_stream`].
+    fn close(&mut self) -> bool {
+        close_stream(&mut self.stream, &mut self.outgoing)
     }
 }
-pub(crate) fn accept_new_connections(listener: &TcpListener, game: &mut GameState) -> Result<(), Error> {
-    fn random_color() -> Color {
-        std::collections::hash_map::DefaultHasher::new().finish() as Color
+
+impl Admin {
+    /// A new admin connection that hasn't authenticated yet.
+    pub(crate) fn new(stream: TcpStream, addr: SocketAddr) -> Self {
+        Admin {
+            addr,
+            stream,
+            incoming: LineBuffer::default(),
+            outgoing: Vec::new(),
+            authenticated: false,
+            rate: RateWindow::default(),
+        }
     }
-    loop {
-        match listener.accept() {
-            Ok((stream, addr)) => {
-                stream.set_nonblocking(true)?;
-                let Some(char) = game.alloc_char(addr) else {
-                    return Err(Error::GameFull);
-                };
-                let con = Connection {
-                    addr,
-                    username: None,
-                    color: random_color(),
-                    char,
-                    stream,
-                    next_stone: None,
-                };
-                game.users.push(con);
+
+    /// Reads everything that is available on the socket into the input buffer.
+    /// Anyone can connect before logging in, so admins that send more than `max_bytes_per_second` are dropped.
+    fn receive(&mut self, max_bytes_per_second: usize) -> Result<(), Error> {
+        let mut chunk = [0; 4096];
+        loop {
+            let budget = self.rate.budget(max_bytes_per_second).min(chunk.len());
+            if budget == 0 {
+                return Err(Error::RateLimited);
             }
-            Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
-                break;
+            match self.stream.read(&mut chunk[..budget]) {
+                Ok(0) => return Err(Error::ConnectionLost),
+                Ok(n) => {
+                    self.rate.received += n;
+                    self.incoming.extend(&chunk[..n]);
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => (),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e.into()),
             }
-            Err(e) => eprintln!("socket error: {e}"),
         }
     }
-    Ok(())
+
+    /// Queues a line to be sent with the next flush.
+    pub(crate) fn send_line(&mut self, line: &str) {
+        self.outgoing.extend_from_slice(line.as_bytes());
+        self.outgoing.push(b'\n');
+    }
+
+    pub(crate) fn flush(&mut self) -> Result<usize, Error> {
+        write_queued(&mut self.stream, &mut self.outgoing)
+    }
+
+    /// Takes a step towards closing the connection, see [`close_stream`].
+    fn close(&mut self) -> bool {
+        close_stream(&mut self.stream, &mut self.outgoing)
+    }
 }
-pub(crate) fn accept_new_ws(listener: &TcpListener, game: &mut GameState) -> Result<(), Error> {
+
+/// Takes a step towards closing a connection without losing what is still queued: writes `outgoing`,
+/// then shuts down the sending side and discards input until the peer closes its side as well.
+/// Returns whether the connection is closed, which includes failing to close it politely.
+fn close_stream(stream: &mut TcpStream, outgoing: &mut Vec<u8>) -> bool {
+    match write_queued(stream, outgoing) {
+        Ok(_) if !outgoing.is_empty() => return false,
+        Ok(_) => (),
+        Err(_) => return true,
+    }
+    // Shutting down the sending side again is harmless, so this doesn't need to be tracked
+    if stream.shutdown(Shutdown::Write).is_err() {
+        return true;
+    }
+    let mut chunk = [0; 4096];
     loop {
-        match listener.accept() {
-            Ok((stream, addr)) => {
-                println!("got new connection from {addr}");
-                let websocket = tungstenite::accept(stream).unwrap();
-                game.frontend = Some(websocket);
+        match stream.read(&mut chunk) {
+            Ok(0) => return true,
+            Ok(_) => (),
+            Err(e) if e.kind() == ErrorKind::Interrupted => (),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => return false,
+            Err(_) => return true,
+        }
+    }
+}
+
+/// Writes as much of `outgoing` as the socket accepts without blocking and removes it from the buffer.
+/// Returns the number of bytes written.
+fn write_queued(stream: &mut TcpStream, outgoing: &mut Vec<u8>) -> Result<usize, Error> {
+    let mut written = 0;
+    while !outgoing.is_empty() {
+        match stream.write(outgoing) {
+            Ok(0) => return Err(Error::ConnectionLost),
+            Ok(n) => {
+                outgoing.drain(..n);
+                written += n;
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => (),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(written)
+}
+
+impl Frontend {
+    /// Whether the frontend is too far behind to take another update.
+    pub(crate) fn is_congested(&self) -> bool {
+        self.queue.len() >= MAX_QUEUED_UPDATES
+    }
+
+    /// Throws away all updates that haven't been started yet. The next update will be a full board.
+    pub(crate) fn skip_queued(&mut self) {
+        self.queue.clear();
+        self.delta.acked_turn = None;
+    }
+
+    pub(crate) fn queue(&mut self, update: Vec<Message>) {
+        self.queue.push_back(update);
+    }
+
+    /// Reads incoming messages, so control frames like pings and closes get handled.
+    /// The frontend doesn't send anything else we care about, frontends sending more than `max_bytes_per_second`
+    /// are dropped.
+    fn receive(&mut self, max_bytes_per_second: usize) -> Result<(), Error> {
+        loop {
+            match self.socket.read().map_err(Error::from) {
+                Ok(message) => {
+                    if message.len() > self.rate.budget(max_bytes_per_second) {
+                        return Err(Error::RateLimited);
+                    }
+                    self.rate.received += message.len();
+                }
+                Err(Error::WouldBlock) => return Ok(()),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Writes queued updates until the socket would block, returning the size of the updates handed to the websocket.
+    /// Only one update at a time is handed to the websocket, the rest stays in our queue.
+    fn flush(&mut self) -> Result<usize, Error> {
+        let mut written = 0;
+        loop {
+            match self.socket.flush().map_err(Error::from) {
+                Err(Error::WouldBlock) => return Ok(written),
+                result => result?,
+            }
+            let Some(update) = self.queue.pop_front() else { return Ok(written) };
+            for message in update {
+                written += message.len();
+                match self.socket.write(message).map_err(Error::from) {
+                    Err(Error::WouldBlock) => (),
+                    result => result?,
+                }
+            }
+        }
+    }
+
+    /// Takes a step towards closing the connection: writes the queued updates, then performs the websocket closing
+    /// handshake. Returns whether the connection is closed, which includes failing to close it politely.
+    fn close(&mut self) -> bool {
+        match self.flush() {
+            Ok(_) if !self.queue.is_empty() => return false,
+            Ok(_) => (),
+            Err(_) => return true,
+        }
+        // Sends the close frame only once, later calls just flush it
+        match self.socket.close(None).map_err(Error::from) {
+            Ok(()) => (),
+            Err(Error::WouldBlock) => return false,
+            Err(_) => return true,
+        }
+        loop {
+            match self.socket.read().map_err(Error::from) {
+                Ok(_) => (),
+                Err(Error::WouldBlock) => return false,
+                // Including the end of the closing handshake
+                Err(_) => return true,
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct FrontendOptions {
+    delta: DeltaState,
+    encoding: Encoding,
+    drop_policy: DropPolicy,
+}
+
+/// Handshake callback remembering the options a frontend asked for in the query string of its request.
+#[derive(Debug, Default, Clone)]
+struct RequestedOptions(Rc<Cell<FrontendOptions>>);
+
+impl Callback for RequestedOptions {
+    fn on_request(self, request: &Request, response: Response) -> Result<Response, ErrorResponse> {
+        let mut options = self.0.get();
+        for param in request.uri().query().unwrap_or_default().split('&') {
+            match param.split_once('=').unwrap_or((param, "")) {
+                ("", "") => (),
+                ("delta", "") => options.delta.enabled = true,
+                ("encoding", "text") => options.encoding = Encoding::Text,
+                ("encoding", "binary") => options.encoding = Encoding::Binary,
+                ("drop", "skip") => options.drop_policy = DropPolicy::Skip,
+                ("drop", "disconnect") => options.drop_policy = DropPolicy::Disconnect,
+                _ => {
+                    let mut response = ErrorResponse::new(Some(format!("invalid query parameter: {param}")));
+                    *response.status_mut() = StatusCode::BAD_REQUEST;
+                    return Err(response);
+                }
+            }
+        }
+        self.0.set(options);
+        Ok(response)
+    }
+}
+
+struct PendingHandshake {
+    addr: SocketAddr,
+    started: Instant,
+    handshake: MidHandshake<ServerHandshake<TcpStream, RequestedOptions>>,
+    options: RequestedOptions,
+}
+
+/// Owns the listening sockets and dispatches readiness events to the connections of the game.
+///
+/// All sockets are non-blocking: input is buffered until the next tick processes it,
+/// and output is queued by the tick and written whenever the socket is writable.
+pub(crate) struct Network {
+    poll: Poll,
+    events: Events,
+    listener: TcpListener,
+    ws_listener: TcpListener,
+    admin_listener: Option<TcpListener>,
+    metrics_listener: Option<TcpListener>,
+    handshakes: HashMap<Token, PendingHandshake>,
+    scrapes: HashMap<Token, Scrape>,
+    /// SIGINT and SIGTERM, which shut the server down.
+    signals: Signals,
+    shutdown_requested: bool,
+    next_token: usize,
+}
+
+impl Network {
+    /// Listens for bots on `addr`, for frontends on `ws_addr` and, if given,
+    /// for admins on `admin_addr` and metrics scrapers on `metrics_addr`.
+    pub(crate) fn bind(
+        addr: SocketAddr,
+        ws_addr: SocketAddr,
+        admin_addr: Option<SocketAddr>,
+        metrics_addr: Option<SocketAddr>,
+    ) -> std::io::Result<Self> {
+        let poll = Poll::new()?;
+        let mut listener = TcpListener::bind(addr)?;
+        let mut ws_listener = TcpListener::bind(ws_addr)?;
+        poll.registry().register(&mut listener, LISTENER, Interest::READABLE)?;
+        poll.registry().register(&mut ws_listener, WS_LISTENER, Interest::READABLE)?;
+        let optional_listener = |addr: Option<SocketAddr>, token| -> std::io::Result<_> {
+            let Some(addr) = addr else { return Ok(None) };
+            let mut listener = TcpListener::bind(addr)?;
+            poll.registry().register(&mut listener, token, Interest::READABLE)?;
+            Ok(Some(listener))
+        };
+        let admin_listener = optional_listener(admin_addr, ADMIN_LISTENER)?;
+        let metrics_listener = optional_listener(metrics_addr, METRICS_LISTENER)?;
+        let mut signals = Signals::new([SIGINT, SIGTERM])?;
+        poll.registry().register(&mut signals, SIGNALS, Interest::READABLE)?;
+        Ok(Network {
+            poll,
+            events: Events::with_capacity(1024),
+            listener,
+            ws_listener,
+            admin_listener,
+            metrics_listener,
+            handshakes: HashMap::new(),
+            scrapes: HashMap::new(),
+            signals,
+            shutdown_requested: false,
+            next_token: SIGNALS.0 + 1,
+        })
+    }
+
+    fn register(&mut self, stream: &mut TcpStream) -> std::io::Result<Token> {
+        let token = Token(self.next_token);
+        self.next_token += 1;
+        self.poll
+            .registry()
+            .register(stream, token, Interest::READABLE | Interest::WRITABLE)?;
+        Ok(token)
+    }
+
+    /// Whether a signal asked the server to shut down.
+    pub(crate) fn shutdown_requested(&self) -> bool {
+        self.shutdown_requested
+    }
+
+    /// Handles network events until `deadline` is reached or a shutdown is requested.
+    pub(crate) fn poll_until(&mut self, game: &mut GameState, deadline: Instant) -> std::io::Result<()> {
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if timeout.is_zero() || self.shutdown_requested {
+                return Ok(());
             }
-            Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
-                break;
+            match self.poll.poll(&mut self.events, Some(timeout)) {
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                result => result?,
+            }
+            let tokens: Vec<_> = self.events.iter().map(|e| e.token()).collect();
+            for token in tokens {
+                match token {
+                    LISTENER => self.accept_new_connections(game),
+                    WS_LISTENER => self.accept_new_ws(game),
+                    ADMIN_LISTENER => self.accept_admins(game),
+                    METRICS_LISTENER => self.accept_scrapes(),
+                    SIGNALS => {
+                        for signal in self.signals.pending() {
+                            info!(signal, "shutdown requested");
+                        }
+                        self.shutdown_requested = true;
+                    }
+                    token if self.handshakes.contains_key(&token) => self.continue_handshake(token, game),
+                    token if self.scrapes.contains_key(&token) => self.continue_scrape(token, game),
+                    token if game.frontends.contains_key(&token) => {
+                        let frontend = game.frontends.get_mut(&token).expect("checked above");
+                        let limit = game.config.max_bytes_per_second;
+                        match frontend.receive(limit).and_then(|_| frontend.flush()) {
+                            Ok(sent) => game.metrics.sent("frontend", sent),
+                            Err(e) => {
+                                info!(addr = %frontend.addr, "lost connection to frontend: {e}");
+                                game.frontends.remove(&token);
+                                game.metrics.dropped("frontend_lost");
+                            }
+                        }
+                    }
+                    token if game.admins.contains_key(&token) => {
+                        let admin = game.admins.get_mut(&token).expect("checked above");
+                        let limit = game.config.max_bytes_per_second;
+                        match admin.receive(limit).and_then(|_| admin.flush()) {
+                            Ok(sent) => game.metrics.sent("admin", sent),
+                            Err(e) => {
+                                info!(addr = %admin.addr, "lost connection to admin: {e}");
+                                game.admins.remove(&token);
+                                game.metrics.dropped("admin_lost");
+                            }
+                        }
+                    }
+                    token => {
+                        let Some(user) = game.users.get_mut(&token) else { continue };
+                        let limit = game.config.max_bytes_per_second;
+                        match user.receive(limit).and_then(|_| user.flush()) {
+                            Ok(sent) => game.metrics.sent("bot", sent),
+                            Err(e) => {
+                                info!(parent: &user.span, "lost connection: {e}");
+                                game.disconnected.push((token, "connection_lost"));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Writes the output queued during the tick, resumes reading from throttled connections
+    /// and gives up on handshakes that take too long.
+    pub(crate) fn flush(&mut self, game: &mut GameState) {
+        let limit = game.config.max_bytes_per_second;
+        let metrics = &mut game.metrics;
+        for (&token, user) in game.users.iter_mut() {
+            let resumed = if user.throttled { user.receive(limit) } else { Ok(()) };
+            match resumed.and_then(|_| user.flush()) {
+                Ok(sent) => metrics.sent("bot", sent),
+                Err(e) => {
+                    info!(parent: &user.span, "lost connection: {e}");
+                    game.disconnected.push((token, "connection_lost"));
+                }
+            }
+        }
+        game.frontends.retain(|_, frontend| match frontend.flush() {
+            Ok(sent) => {
+                metrics.sent("frontend", sent);
+                true
+            }
+            Err(e) => {
+                info!(addr = %frontend.addr, "lost connection to frontend: {e}");
+                metrics.dropped("frontend_lost");
+                false
+            }
+        });
+        game.admins.retain(|_, admin| match admin.flush() {
+            Ok(sent) => {
+                metrics.sent("admin", sent);
+                true
+            }
+            Err(e) => {
+                info!(addr = %admin.addr, "lost connection to admin: {e}");
+                metrics.dropped("admin_lost");
+                false
+            }
+        });
+        self.handshakes.retain(|_, pending| {
+            let alive = pending.started.elapsed() < HANDSHAKE_TIMEOUT;
+            if !alive {
+                warn!(addr = %pending.addr, "websocket handshake timed out");
+                metrics.dropped("handshake_timeout");
+            }
+            alive
+        });
+        self.scrapes.retain(|_, scrape| {
+            let alive = scrape.started.elapsed() < HANDSHAKE_TIMEOUT;
+            if !alive {
+                warn!(addr = %scrape.addr, "metrics request timed out");
+            }
+            alive
+        });
+    }
+
+    /// Delivers what is queued for every connection and closes them all. Gives up on connections that don't finish
+    /// within [`SHUTDOWN_TIMEOUT`], or on all of them when another signal arrives.
+    pub(crate) fn close(&mut self, game: &mut GameState) {
+        self.handshakes.clear();
+        self.scrapes.clear();
+        let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+        loop {
+            game.users.retain(|_, user| !user.close());
+            game.frontends.retain(|_, frontend| !frontend.close());
+            game.admins.retain(|_, admin| !admin.close());
+            let remaining = game.users.len() + game.frontends.len() + game.admins.len();
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if remaining == 0 {
+                return;
+            }
+            if timeout.is_zero() {
+                warn!(remaining, "gave up waiting for connections to close");
+                return;
+            }
+            match self.poll.poll(&mut self.events, Some(timeout)) {
+                Err(e) if e.kind() == ErrorKind::Interrupted => (),
+                Err(e) => {
+                    error!("poll error: {e}");
+                    return;
+                }
+                Ok(()) => (),
+            }
+            if self.events.iter().any(|event| event.token() == SIGNALS) && self.signals.pending().next().is_some() {
+                warn!(remaining, "closing connections aborted by another signal");
+                return;
+            }
+        }
+    }
+
+    fn accept_new_connections(&mut self, game: &mut GameState) {
+        loop {
+            match self.listener.accept() {
+                Ok((mut stream, addr)) => {
+                    if game.bans.contains(&addr.ip()) {
+                        info!(%addr, "rejecting banned connection");
+                        game.metrics.dropped("rejected_banned");
+                        let _ = stream.write(Error::Banned.response().as_bytes());
+                        continue;
+                    }
+                    let max = game.config.max_connections_per_ip;
+                    if game.users.values().filter(|user| user.addr.ip() == addr.ip()).count() >= max {
+                        warn!(%addr, max, "rejecting connection: too many connections from this address");
+                        game.metrics.dropped("rejected_too_many_connections");
+                        // Best effort, the connection is closed right away
+                        let _ = stream.write(Error::TooManyConnections.response().as_bytes());
+                        continue;
+                    }
+                    let token = match self.register(&mut stream) {
+                        Ok(token) => token,
+                        Err(e) => {
+                            error!(%addr, "socket error: {e}");
+                            continue;
+                        }
+                    };
+                    let span = info_span!("bot", %addr, username = field::Empty, player = field::Empty);
+                    // Nobody may skip the queue, waiting connections are admitted in order by the next tick
+                    let id = if game.waiting.is_empty() { game.alloc_player(addr) } else { None };
+                    match id {
+                        Some(id) => {
+                            span.record("player", field::display(player_char(id)));
+                            info!(parent: &span, "new connection");
+                        }
+                        None => {
+                            info!(parent: &span, "new connection has to wait for a free slot");
+                            game.waiting.push_back(token);
+                        }
+                    }
+                    game.users.insert(token, Connection::new(stream, addr, id, span));
+                }
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                    break;
+                }
+                Err(e) => error!("socket error: {e}"),
+            }
+        }
+    }
+
+    fn accept_admins(&mut self, game: &mut GameState) {
+        loop {
+            let Some(admin_listener) = &self.admin_listener else { return };
+            match admin_listener.accept() {
+                Ok((mut stream, addr)) => {
+                    info!(%addr, "new admin connection");
+                    let token = match self.register(&mut stream) {
+                        Ok(token) => token,
+                        Err(e) => {
+                            error!("socket error: {e}");
+                            continue;
+                        }
+                    };
+                    game.admins.insert(token, Admin::new(stream, addr));
+                }
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                    break;
+                }
+                Err(e) => error!("socket error: {e}"),
+            }
+        }
+    }
+
+    fn accept_scrapes(&mut self) {
+        loop {
+            let Some(metrics_listener) = &self.metrics_listener else { return };
+            match metrics_listener.accept() {
+                Ok((mut stream, addr)) => match self.register(&mut stream) {
+                    Ok(token) => drop(self.scrapes.insert(token, Scrape::new(addr, stream))),
+                    Err(e) => error!("socket error: {e}"),
+                },
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                    break;
+                }
+                Err(e) => error!("socket error: {e}"),
+            }
+        }
+    }
+
+    /// Serves the metrics request on `token`, closing the connection once the response is written.
+    fn continue_scrape(&mut self, token: Token, game: &mut GameState) {
+        let Some(scrape) = self.scrapes.get_mut(&token) else { return };
+        match scrape.advance(|| game.render_metrics()) {
+            Ok(false) => (),
+            Ok(true) => drop(self.scrapes.remove(&token)),
+            Err(e) => {
+                debug!(addr = %scrape.addr, "metrics request failed: {e}");
+                self.scrapes.remove(&token);
+            }
+        }
+    }
+
+    fn accept_new_ws(&mut self, game: &mut GameState) {
+        loop {
+            match self.ws_listener.accept() {
+                Ok((mut stream, addr)) => {
+                    info!(%addr, "new websocket connection");
+                    let token = match self.register(&mut stream) {
+                        Ok(token) => token,
+                        Err(e) => {
+                            error!("socket error: {e}");
+                            continue;
+                        }
+                    };
+                    let options = RequestedOptions::default();
+                    let result = tungstenite::accept_hdr(stream, options.clone());
+                    self.handshake_result(token, addr, Instant::now(), result, options, game);
+                }
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                    break;
+                }
+                Err(e) => error!("socket error: {e}"),
+            }
+        }
+    }
+
+    fn continue_handshake(&mut self, token: Token, game: &mut GameState) {
+        let Some(pending) = self.handshakes.remove(&token) else { return };
+        let result = pending.handshake.handshake();
+        self.handshake_result(token, pending.addr, pending.started, result, pending.options, game);
+    }
+
+    fn handshake_result(
+        &mut self,
+        token: Token,
+        addr: SocketAddr,
+        started: Instant,
+        result: Result<WebSocket<TcpStream>, HandshakeError<ServerHandshake<TcpStream, RequestedOptions>>>,
+        options: RequestedOptions,
+        game: &mut GameState,
+    ) {
+        match result {
+            Ok(socket) => {
+                let FrontendOptions {
+                    delta,
+                    encoding,
+                    drop_policy,
+                } = options.0.get();
+                game.frontends.insert(
+                    token,
+                    Frontend {
+                        addr,
+                        socket,
+                        queue: VecDeque::new(),
+                        delta,
+                        encoding,
+                        drop_policy,
+                        rate: RateWindow::default(),
+                    },
+                );
+            }
+            Err(HandshakeError::Interrupted(handshake)) => {
+                let pending = PendingHandshake {
+                    addr,
+                    started,
+                    handshake,
+                    options,
+                };
+                self.handshakes.insert(token, pending);
+            }
+            Err(HandshakeError::Failure(e)) => {
+                warn!(%addr, "websocket handshake failed: {e}");
+                game.metrics.dropped("handshake_failed");
             }
-            Err(e) => eprintln!("socket error: {e}"),
         }
     }
-    Ok(())
 }
+/// The registered usernames. Passwords are only kept as salted hashes, so snapshots don't give them away.
 #[derive(Default, Debug)]
 pub(crate) struct UserAuth {
-    users: HashMap<String, String>,
+    users: HashMap<String, Credentials>,
+}
+
+/// The hex encoded SHA-256 hash of a random salt followed by the password.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Credentials {
+    pub(crate) salt: String,
+    pub(crate) hash: String,
+}
+
+impl Credentials {
+    fn new(password: &str) -> Self {
+        let salt = hex(&rand::random::<[u8; 16]>());
+        let hash = hash_password(&salt, password);
+        Credentials { salt, hash }
+    }
+
+    fn matches(&self, password: &str) -> bool {
+        hash_password(&self.salt, password) == self.hash
+    }
+}
+
+fn hash_password(salt: &str, password: &str) -> String {
+    hex(&Sha256::new().chain_update(salt).chain_update(password).finalize())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
 }
 
 impl UserAuth {
@@ -155,17 +1037,32 @@ impl UserAuth {
         match self.users.entry(username) {
             Entry::Vacant(v) => {
                 let key = v.key().clone();
-                v.insert(password);
+                v.insert(Credentials::new(&password));
                 Some(key)
             }
-            Entry::Occupied(o) => (o.get() == &password).then(|| o.key().clone()),
+            Entry::Occupied(o) => o.get().matches(&password).then(|| o.key().clone()),
         }
     }
+
+    /// Registers a username with the credentials it had before, see [`UserAuth::users`].
+    pub(crate) fn restore(&mut self, username: String, credentials: Credentials) {
+        self.users.insert(username, credentials);
+    }
+
+    /// All known usernames with their hashed passwords.
+    pub(crate) fn users(&self) -> impl Iterator<Item = (&str, &Credentials)> {
+        self.users.iter().map(|(username, credentials)| (username.as_str(), credentials))
+    }
 }
 
 pub(crate) enum FrontendMessage<'a> {
     Board(&'a Board),
-    End,
+    /// A full board for frontends that asked for deltas, tagged with the turn it belongs to.
+    Keyframe(&'a Board, u64),
+    /// Announces a binary encoded board of the given length in the next message.
+    BinaryBoard(&'a Board, u64, usize),
+    Delta(Delta<'a>),
+    End(End<'a>),
 }
 
 impl<'a> Display for FrontendMessage<'a> {
@@ -181,7 +1078,181 @@ impl<'a> Display for FrontendMessage<'a> {
                     board.serialize()
                 )
             }
-            FrontendMessage::End => write!(f, "END"),
+            FrontendMessage::Keyframe(board, turn) => {
+                write!(f, "{} {turn}", FrontendMessage::Board(board))
+            }
+            FrontendMessage::BinaryBoard(board, turn, len) => {
+                write!(f, "BINBOARD {:?} {turn} {len}", board.unix_timestamp())
+            }
+            FrontendMessage::Delta(delta) => delta.fmt(f),
+            FrontendMessage::End(end) => end.fmt(f),
+        }
+    }
+}
+
+/// The final scores, sent to bots and frontends when the server shuts down.
+pub(crate) struct End<'a> {
+    pub(crate) scores: &'a [(PlayerId, usize)],
+    pub(crate) encoding: Encoding,
+}
+
+impl<'a> Display for End<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "END")?;
+        for &(id, score) in self.scores {
+            match self.encoding {
+                Encoding::Text => write!(f, " {} {score}", player_char(id))?,
+                Encoding::Binary => write!(f, " {id} {score}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The tiles that changed between the turns `base` and `turn`.
+pub(crate) struct Delta<'a> {
+    pub(crate) board: &'a Board,
+    pub(crate) turn: u64,
+    pub(crate) base: u64,
+    pub(crate) changes: &'a [usize],
+    pub(crate) encoding: Encoding,
+}
+
+impl<'a> Display for Delta<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "DELTA {} {} {}", self.turn, self.base, self.changes.len())?;
+        if !self.changes.is_empty() {
+            write!(f, " {}", self.board.serialize_changes(self.changes, self.encoding))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use super::*;
+
+    fn puts(line: &str) -> Result<Vec<Position>, Error> {
+        match line.parse()? {
+            Command::Puts(positions) => Ok(positions),
+            command => panic!("{line} parsed as {command:?}"),
+        }
+    }
+
+    #[test]
+    fn puts_takes_pairs_of_coordinates() {
+        assert_eq!(puts("PUTS 1 2").ok(), Some(vec![(1, 2)]));
+        assert_eq!(puts("PUTS 1 2 3 4 1 2").ok(), Some(vec![(1, 2), (3, 4), (1, 2)]));
+        assert_eq!(puts("PUTS 0 65535").ok(), Some(vec![(0, u16::MAX)]));
+    }
+
+    #[test]
+    fn invalid_puts_are_rejected() {
+        for line in ["PUTS", "PUTS ", "PUTS 1", "PUTS 1 2 3", "PUTS 1  2", "PUTS 1 2 ", "PUTS -1 2", "PUTS 1 65536"] {
+            assert!(matches!(line.parse::<Command>(), Err(Error::InvalidArgument)), "{line}");
+        }
+        assert!(matches!("PUTS1 2".parse::<Command>(), Err(Error::UnknownCommand)));
+    }
+
+    #[test]
+    fn admins_sending_too_much_are_dropped() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("free port");
+        let mut client = std::net::TcpStream::connect(listener.local_addr().expect("bound")).expect("connected");
+        let (stream, addr) = listener.accept().expect("accepted");
+        stream.set_nonblocking(true).expect("nonblocking");
+        let mut admin = Admin::new(TcpStream::from_std(stream), addr);
+        client.write_all(b"AUTH secret\n").expect("sent");
+        while admin.incoming.next_line().is_none() {
+            admin.receive(100).expect("within the limit");
+        }
+        client.write_all(&[b'x'; 100]).expect("sent");
+        let mut result = Ok(());
+        while result.is_ok() {
+            result = admin.receive(100);
+        }
+        assert!(matches!(result, Err(Error::RateLimited)));
+    }
+
+    /// An error of every kind. The match makes sure that new kinds are added here as well.
+    fn every_error() -> Vec<Error> {
+        let errors = vec![
+            Error::UnknownCommand,
+            Error::InvalidArgument,
+            Error::InvalidCredentials,
+            Error::NotPlaying,
+            Error::Unauthorized,
+            Error::GameStopped,
+            Error::InvalidMap("the map is empty"),
+            Error::ConnectionLost,
+            Error::WouldBlock,
+            Error::LineTooLong,
+            Error::SendBufferFull,
+            Error::TooManyCommands(3),
+            Error::TooManyQueries,
+            Error::RateLimited,
+            Error::TooManyInvalidCommands,
+            Error::TooManyConnections,
+            Error::Kicked,
+            Error::Banned,
+            Error::IO(std::io::Error::other("disk on fire")),
+            Error::Utf8(String::from_utf8(vec![0xff]).expect_err("invalid utf-8").utf8_error()),
+            Error::WebSocket(Box::new(tungstenite::Error::AlreadyClosed)),
+        ];
+        for error in &errors {
+            match error {
+                Error::UnknownCommand
+                | Error::InvalidArgument
+                | Error::InvalidCredentials
+                | Error::NotPlaying
+                | Error::Unauthorized
+                | Error::GameStopped
+                | Error::InvalidMap(_)
+                | Error::ConnectionLost
+                | Error::WouldBlock
+                | Error::LineTooLong
+                | Error::SendBufferFull
+                | Error::TooManyCommands(_)
+                | Error::TooManyQueries
+                | Error::RateLimited
+                | Error::TooManyInvalidCommands
+                | Error::TooManyConnections
+                | Error::Kicked
+                | Error::Banned
+                | Error::IO(_)
+                | Error::Utf8(_)
+                | Error::WebSocket(_) => (),
+            }
+        }
+        errors
+    }
+
+    #[test]
+    fn error_codes_match_the_readme() {
+        // The rows of the error table, like "| `101` | `invalid argument` | ... |"
+        let table: BTreeMap<u16, &str> = include_str!("../../README.md")
+            .lines()
+            .filter_map(|line| {
+                let mut cells = line.strip_prefix("| `")?.split("` | `");
+                let code = cells.next()?.parse().ok()?;
+                Some((code, cells.next()?.split("` |").next()?))
+            })
+            .collect();
+        let mut documented = BTreeSet::new();
+        for error in every_error() {
+            let (code, message) = (error.code(), error.to_string());
+            if code >= 300 {
+                assert!(!table.contains_key(&code), "{code} is internal");
+                continue;
+            }
+            let row = table.get(&code).unwrap_or_else(|| panic!("{code} is missing from the README"));
+            // Details are written like `<reason>` in the README
+            let fixed = row.split_once(" <").map_or(*row, |(fixed, _)| fixed);
+            assert!(message.starts_with(fixed), "{code}: {message} doesn't match {row}");
+            assert_eq!(row.contains('<'), message != fixed, "{code}: {message} doesn't match {row}");
+            documented.insert(code);
         }
+        assert_eq!(documented, table.keys().copied().collect());
     }
 }
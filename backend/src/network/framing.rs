@@ -0,0 +1,198 @@
+use std::collections::VecDeque;
+
+use super::Error;
+
+/// Lines longer than this, not counting the line ending, are rejected.
+pub(crate) const MAX_LINE_LENGTH: usize = 1024;
+
+/// Splits the input of a connection into lines, terminated by `\n` or `\r\n`.
+///
+/// Lines that get longer than [`MAX_LINE_LENGTH`] are reported as [`Error::LineTooLong`] as soon as the limit is
+/// exceeded, and the rest of them is skipped, so the buffered input never grows beyond a single line.
+#[derive(Debug, Default)]
+pub(crate) struct LineBuffer {
+    lines: VecDeque<Result<Vec<u8>, Error>>,
+    partial: Vec<u8>,
+    skipping: bool,
+}
+
+impl LineBuffer {
+    pub(crate) fn extend(&mut self, data: &[u8]) {
+        for segment in data.split_inclusive(|&b| b == b'\n') {
+            let (content, terminated) = match segment.split_last() {
+                Some((b'\n', content)) => (content, true),
+                _ => (segment, false),
+            };
+            if !self.skipping {
+                self.partial.extend_from_slice(content);
+                // One more byte is fine, it might be the `\r` of a `\r\n`
+                if self.partial.len() > MAX_LINE_LENGTH + 1 {
+                    self.partial.clear();
+                    self.skipping = true;
+                    self.lines.push_back(Err(Error::LineTooLong));
+                }
+            }
+            if terminated && !std::mem::take(&mut self.skipping) {
+                let mut line = std::mem::take(&mut self.partial);
+                if line.last() == Some(&b'\r') {
+                    line.pop();
+                }
+                if line.len() > MAX_LINE_LENGTH {
+                    self.lines.push_back(Err(Error::LineTooLong));
+                } else if !line.is_empty() {
+                    self.lines.push_back(Ok(line));
+                }
+            }
+        }
+    }
+
+    /// Throws away all complete lines that haven't been read yet and returns how many there were.
+    pub(crate) fn discard(&mut self) -> usize {
+        let count = self.lines.len();
+        self.lines.clear();
+        count
+    }
+
+    /// Returns the next complete line, skipping empty ones.
+    pub(crate) fn next_line(&mut self) -> Option<Result<String, Error>> {
+        let line = self.lines.pop_front()?;
+        Some(line.and_then(|line| String::from_utf8(line).map_err(|e| e.utf8_error().into())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The lines `input` should be split into if it was received in one piece.
+    fn reference(input: &[u8]) -> Vec<Option<Vec<u8>>> {
+        let mut segments: Vec<&[u8]> = input.split(|&b| b == b'\n').collect();
+        let unterminated = segments.pop().unwrap_or_default();
+        let mut lines: Vec<_> = segments
+            .into_iter()
+            .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+            .filter(|line| !line.is_empty())
+            .map(|line| (line.len() <= MAX_LINE_LENGTH).then(|| line.to_vec()))
+            .collect();
+        if unterminated.len() > MAX_LINE_LENGTH + 1 {
+            lines.push(None);
+        }
+        lines
+    }
+
+    fn drain(buffer: &mut LineBuffer) -> Vec<Option<Vec<u8>>> {
+        std::iter::from_fn(|| buffer.lines.pop_front())
+            .map(|line| match line {
+                Ok(line) => Some(line),
+                Err(Error::LineTooLong) => None,
+                Err(e) => panic!("unexpected error {e}"),
+            })
+            .collect()
+    }
+
+    /// A small xorshift generator, so the fuzz tests are reproducible without extra dependencies.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+    }
+
+    /// Random input biased towards the interesting cases: line endings, long lines and invalid UTF-8.
+    fn random_input(rng: &mut Rng) -> Vec<u8> {
+        let mut input = Vec::new();
+        for _ in 0..rng.below(20) {
+            let len = match rng.below(4) {
+                0 => rng.below(8),
+                1 => MAX_LINE_LENGTH - 2 + rng.below(5),
+                2 => rng.below(3 * MAX_LINE_LENGTH),
+                _ => rng.below(64),
+            };
+            input.extend((0..len).map(|_| match rng.below(10) {
+                0 => b'\r',
+                1 => 0xff,
+                _ => b'a' + rng.below(26) as u8,
+            }));
+            match rng.below(4) {
+                0 => input.extend_from_slice(b"\r\n"),
+                1 => (),
+                _ => input.push(b'\n'),
+            }
+        }
+        input
+    }
+
+    #[test]
+    fn splits_lines() {
+        let mut buffer = LineBuffer::default();
+        buffer.extend(b"PUT 1 2\nPUT 3");
+        buffer.extend(b" 4\r\n\r\n\nLOGIN a b\n");
+        let lines: Vec<_> = std::iter::from_fn(|| buffer.next_line()).map(Result::unwrap).collect();
+        assert_eq!(lines, ["PUT 1 2", "PUT 3 4", "LOGIN a b"]);
+        assert!(buffer.next_line().is_none());
+    }
+
+    #[test]
+    fn invalid_utf8_is_consumed() {
+        let mut buffer = LineBuffer::default();
+        buffer.extend(b"\xff\xfe\nPUT 1 1\n");
+        assert!(matches!(buffer.next_line(), Some(Err(Error::Utf8(_)))));
+        assert_eq!(buffer.next_line().unwrap().unwrap(), "PUT 1 1");
+    }
+
+    #[test]
+    fn overlong_line_is_reported_once_and_skipped() {
+        let mut buffer = LineBuffer::default();
+        for _ in 0..10 {
+            buffer.extend(&[b'x'; MAX_LINE_LENGTH]);
+        }
+        assert!(matches!(buffer.next_line(), Some(Err(Error::LineTooLong))));
+        assert!(buffer.next_line().is_none());
+        assert!(buffer.partial.is_empty());
+        buffer.extend(b"xxx\nPUT 0 0\n");
+        assert_eq!(buffer.next_line().unwrap().unwrap(), "PUT 0 0");
+    }
+
+    #[test]
+    fn longest_line_is_accepted() {
+        let mut buffer = LineBuffer::default();
+        let line = vec![b'x'; MAX_LINE_LENGTH];
+        buffer.extend(&line);
+        buffer.extend(b"\r\n");
+        buffer.extend(&line);
+        buffer.extend(b"x\n");
+        assert_eq!(buffer.next_line().unwrap().unwrap().len(), MAX_LINE_LENGTH);
+        assert!(matches!(buffer.next_line(), Some(Err(Error::LineTooLong))));
+    }
+
+    #[test]
+    fn fuzz_matches_reference() {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..500 {
+            let input = random_input(&mut rng);
+            let mut whole = LineBuffer::default();
+            whole.extend(&input);
+            let expected = reference(&input);
+            assert_eq!(drain(&mut whole), expected);
+
+            // The result must not depend on how the input is split into reads
+            let mut chunked = LineBuffer::default();
+            let mut rest = &input[..];
+            while !rest.is_empty() {
+                let (chunk, tail) = rest.split_at(1 + rng.below(rest.len().min(2 * MAX_LINE_LENGTH)));
+                chunked.extend(chunk);
+                assert!(chunked.partial.len() <= MAX_LINE_LENGTH + 1);
+                rest = tail;
+            }
+            assert_eq!(drain(&mut chunked), expected);
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use std::io::{ErrorKind, Read};
+use std::net::SocketAddr;
+use std::time::Instant;
+
+use mio::net::TcpStream;
+
+use super::{write_queued, Error};
+
+/// Requests with headers larger than this are rejected.
+const MAX_REQUEST_SIZE: usize = 8192;
+
+/// A request to the metrics endpoint. Only a single request is served per connection, which is closed afterwards.
+pub(super) struct Scrape {
+    pub(super) addr: SocketAddr,
+    pub(super) started: Instant,
+    stream: TcpStream,
+    request: Vec<u8>,
+    /// The response still to be written, once the request is complete.
+    response: Option<Vec<u8>>,
+}
+
+impl Scrape {
+    pub(super) fn new(addr: SocketAddr, stream: TcpStream) -> Self {
+        Scrape {
+            addr,
+            started: Instant::now(),
+            stream,
+            request: Vec::new(),
+            response: None,
+        }
+    }
+
+    /// Reads the request and writes the response, calling `metrics` for the body if the metrics were requested.
+    /// Returns whether the response was written completely, so the connection can be closed.
+    pub(super) fn advance(&mut self, metrics: impl FnOnce() -> String) -> Result<bool, Error> {
+        let response = match &mut self.response {
+            Some(response) => response,
+            None => {
+                if !self.receive()? {
+                    return Ok(false);
+                }
+                self.response.insert(respond(&self.request, metrics))
+            }
+        };
+        write_queued(&mut self.stream, response)?;
+        Ok(response.is_empty())
+    }
+
+    /// Reads what is available, returning whether the request headers are complete.
+    fn receive(&mut self) -> Result<bool, Error> {
+        let mut chunk = [0; 1024];
+        loop {
+            if self.request.windows(4).any(|w| w == b"\r\n\r\n") || self.request.len() > MAX_REQUEST_SIZE {
+                return Ok(true);
+            }
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Err(Error::ConnectionLost),
+                Ok(n) => self.request.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == ErrorKind::Interrupted => (),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(false),
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+fn respond(request: &[u8], metrics: impl FnOnce() -> String) -> Vec<u8> {
+    let request_line = request.split(|&b| b == b'\r').next().unwrap_or_default();
+    let mut parts = request_line.split(|&b| b == b' ');
+    let (status, content_type, body) = match (parts.next(), parts.next()) {
+        _ if request.len() > MAX_REQUEST_SIZE => ("431 Request Header Fields Too Large", "text/plain", String::new()),
+        (Some(b"GET"), Some(b"/metrics")) => ("200 OK", "text/plain; version=0.0.4", metrics()),
+        (Some(b"GET"), _) => ("404 Not Found", "text/plain", "Metrics are served at /metrics\n".to_owned()),
+        _ => ("405 Method Not Allowed", "text/plain", String::new()),
+    };
+    let head = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    );
+    [head.into_bytes(), body.into_bytes()].concat()
+}
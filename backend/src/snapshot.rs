@@ -0,0 +1,253 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use tracing::warn;
+
+use crate::config::TICK_MILLIS;
+use crate::game::{Board, History, PlayerId};
+use crate::network::Credentials;
+use crate::GameState;
+
+/// The first line of every snapshot, the number is bumped when the format changes.
+const HEADER: &str = "GOTO SNAPSHOT 1";
+
+/// The lines of a snapshot by their first word, see the README for the format.
+pub(crate) struct Fields<'a> {
+    lines: BTreeMap<&'a str, Vec<&'a str>>,
+}
+
+impl<'a> Fields<'a> {
+    fn parse(snapshot: &'a str) -> Result<Self, String> {
+        let mut lines = snapshot.lines();
+        if lines.next() != Some(HEADER) {
+            return Err(format!("not a snapshot, expected it to start with `{HEADER}`"));
+        }
+        let mut fields = Fields { lines: BTreeMap::new() };
+        for line in lines.filter(|line| !line.is_empty()) {
+            let (key, rest) = line.split_once(' ').unwrap_or((line, ""));
+            fields.lines.entry(key).or_default().push(rest);
+        }
+        Ok(fields)
+    }
+
+    /// The rest of the line starting with `key`, which has to appear exactly once.
+    pub(crate) fn one(&self, key: &str) -> Result<&'a str, String> {
+        match self.all(key) {
+            [line] => Ok(line),
+            [] => Err(format!("{key} is missing")),
+            _ => Err(format!("{key} appears more than once")),
+        }
+    }
+
+    /// The rest of all lines starting with `key`, in the order they appear.
+    pub(crate) fn all(&self, key: &str) -> &[&'a str] {
+        self.lines.get(key).map_or(&[], Vec::as_slice)
+    }
+}
+
+pub(crate) fn value<T: FromStr>(key: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("invalid value for {key}: {value}"))
+}
+
+/// Parses space separated values.
+pub(crate) fn values<T: FromStr>(key: &str, line: &str) -> Result<Vec<T>, String> {
+    line.split_whitespace().map(|v| value(key, v)).collect()
+}
+
+fn yes_no(key: &str, value: &str) -> Result<bool, String> {
+    match value {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err(format!("{key} must be yes or no")),
+    }
+}
+
+impl GameState {
+    /// Serializes everything needed to continue the game after a restart.
+    /// Connections aren't saved, instead players get their slot back when they log in with the same username.
+    pub(crate) fn snapshot(&self) -> String {
+        let yes_no = |b| if b { "yes" } else { "no" };
+        let mut lines = vec![
+            HEADER.to_owned(),
+            format!("TICK {}", self.tick.as_millis()),
+            format!("RUNNING {}", yes_no(self.running)),
+            format!("PAUSED {}", yes_no(self.paused)),
+            format!("MAP {} {} {}", self.map.width, self.map.height, self.map.serialize()),
+        ];
+        self.board.write_snapshot(&mut lines);
+        self.history.write_snapshot(&mut lines);
+        let users = self.user_auth.users();
+        lines.extend(users.map(|(username, Credentials { salt, hash })| format!("USER {username} {salt} {hash}")));
+        let playing = self.users.values().filter_map(|user| Some((user.id?, user.username.as_deref()?)));
+        let reserved = self.reserved.iter().map(|(username, &id)| (id, username.as_str()));
+        let mut players: Vec<_> = playing.chain(reserved).collect();
+        players.sort_unstable();
+        lines.extend(players.iter().map(|(id, username)| format!("PLAYER {id} {username}")));
+        let budgets = self.budgets.iter().enumerate().filter(|&(_, &saved)| saved > 0);
+        lines.extend(budgets.map(|(id, saved)| format!("BUDGET {id} {saved}")));
+        let handicaps = self.handicaps.iter().enumerate().filter(|&(_, &left)| left > 0);
+        lines.extend(handicaps.map(|(id, left)| format!("HANDICAP {id} {left}")));
+        lines.extend(self.bans.iter().map(|ip| format!("BANNED {ip}")));
+        lines.push(String::new());
+        lines.join("\n")
+    }
+
+    /// Writes the snapshot to a temporary file that replaces `path` once complete,
+    /// so a crash while saving never leaves a broken snapshot behind.
+    pub(crate) fn save_snapshot(&self, path: &Path) -> io::Result<()> {
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        fs::write(&temporary, self.snapshot())?;
+        fs::rename(&temporary, path)
+    }
+
+    /// Continues the game saved in `snapshot`. Must be called before anyone connects.
+    pub(crate) fn resume(&mut self, snapshot: &str) -> Result<(), String> {
+        let fields = Fields::parse(snapshot)?;
+        let millis = value("TICK", fields.one("TICK")?)?;
+        if !TICK_MILLIS.contains(&millis) {
+            return Err(format!("TICK must be between {} and {}", TICK_MILLIS.start(), TICK_MILLIS.end()));
+        }
+        self.tick = Duration::from_millis(millis);
+        self.running = yes_no("RUNNING", fields.one("RUNNING")?)?;
+        self.paused = yes_no("PAUSED", fields.one("PAUSED")?)?;
+
+        let map: Vec<_> = fields.one("MAP")?.split(' ').collect();
+        let [width, height, tiles] = map[..] else {
+            return Err("MAP needs a width, a height and the tiles".to_owned());
+        };
+        let width: usize = value("MAP", width)?;
+        let height: usize = value("MAP", height)?;
+        if tiles.chars().count() != width * height {
+            return Err(format!("MAP has {} tiles instead of {}", tiles.chars().count(), width * height));
+        }
+        let rows: Vec<String> = tiles.chars().collect::<Vec<_>>().chunks(width.max(1)).map(String::from_iter).collect();
+        self.map = Board::from_map(&rows.join("\n")).map_err(|e| format!("invalid MAP: {e}"))?;
+        self.board = Board::from_snapshot(&fields)?;
+        self.history = History::from_snapshot(&fields, &self.board)?;
+
+        for user in fields.all("USER") {
+            let [username, salt, hash] = user.split(' ').collect::<Vec<_>>()[..] else {
+                return Err("USER needs a username, a salt and a password hash".to_owned());
+            };
+            let credentials = Credentials { salt: salt.to_owned(), hash: hash.to_owned() };
+            self.user_auth.restore(username.to_owned(), credentials);
+        }
+        for player in fields.all("PLAYER") {
+            let (id, username) = player.split_once(' ').ok_or("PLAYER needs an id and a username")?;
+            let id: PlayerId = value("PLAYER", id)?;
+            if usize::from(id) < self.players.len() {
+                self.reserved.insert(username.to_owned(), id);
+            } else {
+                warn!(id, username, "player doesn't fit into the game anymore, see --max-players");
+            }
+        }
+        for budget in fields.all("BUDGET") {
+            let [id, saved] = values::<usize>("BUDGET", budget)?[..] else {
+                return Err("BUDGET needs an id and the saved stones".to_owned());
+            };
+            if let Some(budget) = self.budgets.get_mut(id) {
+                *budget = saved;
+            }
+        }
+        for handicap in fields.all("HANDICAP") {
+            let [id, left] = values::<usize>("HANDICAP", handicap)?[..] else {
+                return Err("HANDICAP needs an id and the stones left".to_owned());
+            };
+            if let Some(handicap) = self.handicaps.get_mut(id) {
+                *handicap = left;
+            }
+        }
+        for ban in fields.all("BANNED") {
+            self.bans.insert(value("BANNED", ban)?);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::game::{Capture, Suicide};
+
+    /// A game a few turns in, with something saved in every kind of line.
+    fn game() -> GameState {
+        let mut game = GameState::new(5, Config::default());
+        game.map = Board::from_map("..../\n.....\n.#...\n.....\n.....").expect("valid map");
+        game.board = game.map.clone();
+        game.history.reset(&game.board);
+        for (id, x, y) in [(0, 1, 1), (1, 2, 1), (0, 1, 3), (1, 3, 3)] {
+            game.board.try_place(x, y, id, Suicide::Before).expect("legal move");
+            let placed = game.board.place_stones(&[(id, x, y)]);
+            game.board.capture(&placed, Capture::Remove, false);
+            game.board.age_stones();
+            game.history.record(&game.board);
+        }
+        game.board.protect(4, 4, 1, 3);
+        game.user_auth.is_valid_or_insert("alice".to_owned(), "secret".to_owned());
+        game.reserved.insert("alice".to_owned(), 0);
+        game.budgets[0] = 2;
+        game.handicaps[1] = 1;
+        game.bans.insert("10.0.0.1".parse().expect("valid ip"));
+        game.paused = true;
+        game
+    }
+
+    /// The liberties of the group of every stone, the union find doesn't keep track of empty fields.
+    fn liberties(game: &mut GameState) -> Vec<String> {
+        let fields = game.board.render_liberties();
+        fields.split_whitespace().filter(|field| !field.starts_with(['.', '/'])).map(str::to_owned).collect()
+    }
+
+    #[test]
+    fn snapshots_round_trip() {
+        let mut before = game();
+        let snapshot = before.snapshot();
+        let mut after = GameState::new(5, Config::default());
+        after.resume(&snapshot).expect("valid snapshot");
+        assert_eq!(after.snapshot(), snapshot);
+        assert_eq!(liberties(&mut after), liberties(&mut before));
+        assert_eq!(after.board.serialize_ages(), before.board.serialize_ages());
+        assert_eq!(after.reserved, before.reserved);
+        assert!(after.paused);
+    }
+
+    #[test]
+    fn passwords_are_only_saved_as_salted_hashes() {
+        let snapshot = game().snapshot();
+        assert!(!snapshot.contains("secret"));
+        let mut after = GameState::new(5, Config::default());
+        after.resume(&snapshot).expect("valid snapshot");
+        let mut login = |password: &str| after.user_auth.is_valid_or_insert("alice".to_owned(), password.to_owned());
+        assert_eq!(login("wrong"), None);
+        assert_eq!(login("secret").as_deref(), Some("alice"));
+        // The same password gets a different salt every time it is registered
+        let user = |snapshot: &str| snapshot.lines().find(|line| line.starts_with("USER ")).map(str::to_owned);
+        assert_ne!(user(&game().snapshot()), user(&snapshot));
+    }
+
+    #[test]
+    fn snapshots_with_other_headers_are_rejected() {
+        let snapshot = game().snapshot();
+        for header in ["GOTO SNAPSHOT 2", "GOTO SNAPSHOT", "SOMETHING ELSE", ""] {
+            let result = GameState::new(5, Config::default()).resume(&snapshot.replacen(HEADER, header, 1));
+            assert!(result.is_err(), "{header}");
+        }
+    }
+
+    #[test]
+    fn broken_snapshots_are_rejected() {
+        let snapshot = game().snapshot();
+        let resume = |snapshot: &str| GameState::new(5, Config::default()).resume(snapshot);
+        assert!(resume(&snapshot.replace(HEADER, "GOTO SNAPSHOT 0")).is_err());
+        assert!(resume(&snapshot.replace("TICK 100", "TICK 0")).is_err());
+        assert!(resume(&snapshot.replace("\nAGES ", "\nAGES 1 ")).is_err());
+        assert!(resume(&snapshot.replace("\nUSER alice ", "\nUSER alice")).is_err());
+        assert!(resume(&snapshot.replace("PROTECTED 24 1 3", "PROTECTED 25 1 3")).is_err());
+    }
+}
@@ -1,14 +1,15 @@
 use crate::Command;
 
 pub struct GameState {
-    id: u8,
+    /// Our player, `None` while we wait for a free slot.
+    id: Option<u16>,
     map: Map,
 }
 
 impl GameState {
     pub fn new() -> GameState {
         GameState {
-            id: 0,
+            id: None,
             map: Map::new(10, 10),
         }
     }
@@ -20,15 +21,30 @@ impl GameState {
                 let (x, data) = data.split_once(' ').unwrap();
                 let (y, data) = data.split_once(' ').unwrap();
                 self.map = Map::parse(x.parse().unwrap(), y.parse().unwrap(), data).expect("Failed to parse map");
-                self.id = char.chars().nth(0).unwrap() as u8 - b'0';
+                // Waiting connections are sent `-` instead of a player
+                self.id = match char.chars().next().filter(|&c| c != '-').map(Tile::from_char) {
+                    Some(Tile::Player(id)) => Some(id),
+                    _ => None,
+                };
                 self.generate_response();
             }
             _ => panic!("Unknown response: {}", response),
         }
     }
 
+    /// Extends one of our groups if possible, otherwise takes the first empty field.
     fn generate_response(&self) -> Command {
-        Command::Put(0, 0)
+        let fields = (0..self.map.height).flat_map(|y| (0..self.map.width).map(move |x| (x, y)));
+        let empty: Vec<_> = fields.filter(|&(x, y)| matches!(self.map.get(x, y), Tile::Empty)).collect();
+        let own = |x: u16, y: u16| matches!(self.map.get(x, y), Tile::Player(id) if Some(id) == self.id);
+        let next_to_own = empty.iter().find(|&&(x, y)| {
+            (x > 0 && own(x - 1, y))
+                || (y > 0 && own(x, y - 1))
+                || (x + 1 < self.map.width && own(x + 1, y))
+                || (y + 1 < self.map.height && own(x, y + 1))
+        });
+        let (x, y) = next_to_own.or(empty.first()).copied().unwrap_or_default();
+        Command::Put(u32::from(x), u32::from(y))
     }
 }
 
@@ -66,7 +82,8 @@ pub enum Tile {
     #[default]
     Empty,
     Wall,
-    Player(u8),
+    Neutral,
+    Player(u16),
 }
 
 impl Tile {
@@ -74,7 +91,10 @@ impl Tile {
         match c {
             '.' => Tile::Empty,
             '/' => Tile::Wall,
-            'A'..='z' => Tile::Player(c as u8 - b'A'),
+            '#' => Tile::Neutral,
+            'A'..='Z' => Tile::Player(c as u16 - 'A' as u16),
+            'a'..='z' => Tile::Player(c as u16 - 'a' as u16 + 26),
+            '\u{4e00}'..='\u{9fff}' => Tile::Player((c as u32 - 0x4e00) as u16 + 52),
             _ => panic!("Unknown tile type: {}", c),
         }
     }
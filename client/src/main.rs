@@ -48,7 +48,7 @@ trait CommandSink {
 
 impl<T: IOWrite> CommandSink for T {
     fn send_command(&mut self, command: Command) -> std::io::Result<()> {
-        println!("  <- {}", command.to_string());
+        println!("  <- {command}");
         writeln!(self, "{command}")?;
         self.flush()
     }
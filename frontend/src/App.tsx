@@ -1,12 +1,17 @@
 import { Board } from "Board"
-import { GameStateContext, GameStateProvider, parseMsg } from "lib/game";
+import { GameStateContext, GameStateProvider, applyDelta, parseMsg } from "lib/game";
 import { WebSocketContext, WebSocketProvider, } from "lib/ws";
 import { QRCodeSVG } from "qrcode.react"
 import { useContext, useEffect, useState } from "react";
 
+/** Delta updates are opt-in: open the page with `?delta` to receive them instead of a full board every turn. */
+const SERVER_URL = new URLSearchParams(window.location.search).has("delta")
+    ? "ws://localhost:1213/?delta"
+    : "ws://localhost:1213";
+
 export function App() {
     return <GameStateProvider>
-        <WebSocketProvider url="ws://localhost:1213">
+        <WebSocketProvider url={SERVER_URL}>
 
             <div className="flex justify-center items-center w-dvw h-dvh">
                 <div className="flex flex-row h-dvh py-10">
@@ -34,6 +39,11 @@ function Game() {
         turn: state.turn + 1
     })));
 
+    websocket?.registerHandler("DELTA", (msg) => gameState?.setState((state) => ({
+        board: applyDelta(state.board, msg),
+        turn: state.turn + 1
+    })));
+
     const width = gameState?.board.width || 3;
     const height = gameState?.board.height || 3;
 
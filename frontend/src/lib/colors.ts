@@ -40,7 +40,35 @@ export const COLORS = [
     "#20f6ba", "#07d7f6", "#dce77a", "#77ecca"
 ];
 
-export function getColorFromChar(char: string): string {
-    return COLORS[char.charCodeAt(0) - 65];
+/**
+ * Maps the character of a player back to its number:
+ * `A` to `Z` are 0 to 25, `a` to `z` 26 to 51 and CJK ideographs starting at U+4E00 everyone else.
+ */
+export function getPlayerFromChar(char: string): number {
+    const code = char.codePointAt(0) ?? 0;
+    if (code >= 0x4e00) return code - 0x4e00 + 52;
+    if (code >= 97) return code - 97 + 26;
+    return code - 65;
+}
+
+export const WALL_COLOR = "#3a3a3a";
+export const NEUTRAL_COLOR = "#9e9e9e";
+
+/**
+ * The colour of a field, or `null` for fields without anything to draw:
+ * empty fields (`.`) and fields hidden by the fog of war (`?`).
+ */
+export function getColorFromChar(char: string): string | null {
+    switch (char) {
+        case '.':
+        case '?':
+            return null;
+        case '/':
+            return WALL_COLOR;
+        case '#':
+            return NEUTRAL_COLOR;
+        default:
+            return COLORS[getPlayerFromChar(char) % COLORS.length];
+    }
 }
 
@@ -50,13 +50,32 @@ export function parseMsg(msg: string[]): Board {
 
 export function parseBoard(encBoard: string, width: number, height: number): Omit<Board, 'start'> {
     const board = encBoard.split('')
-        .map((color, index) => ({
+        .map((char, index) => ({
             x: index % width,
             y: height - 1 - Math.floor((index) / width),
-            color
+            color: getColorFromChar(char)
         }))
-        .filter(piece => piece.color !== '.')
-        .map(piece => ({ ...piece, color: getColorFromChar(piece.color) }))
+        .filter((piece): piece is Player => piece.color !== null)
 
     return { board, width, height }
 }
+
+export function applyDelta(board: Board, msg: string[]): Board {
+    const [, , countStr, ...changes] = msg;
+    const count = parseInt(countStr);
+
+    const updated = new Map(board.board.map(piece => [`${piece.x}-${piece.y}`, piece]));
+    for (let i = 0; i < count; i++) {
+        const [xStr, yStr, color] = changes.slice(i * 3, i * 3 + 3);
+        const x = parseInt(xStr);
+        const y = board.height - 1 - parseInt(yStr);
+        const tile = getColorFromChar(color);
+        if (tile === null) {
+            updated.delete(`${x}-${y}`);
+        } else {
+            updated.set(`${x}-${y}`, { x, y, color: tile });
+        }
+    }
+
+    return { ...board, board: [...updated.values()] }
+}